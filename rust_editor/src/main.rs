@@ -1,71 +1,163 @@
 // Importing necessary modules from the Crossterm for Cross-terminal compatibility
 use crossterm::{
     cursor, // For controlling the cursor
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, KeyEventKind}, // For handling keyboard/mouse events
-    execute, // Macro to execute a batch of terminal commands
-    style::{Print, Color, Stylize}, // To print styled or plain text
-    terminal::{self, Clear, ClearType, disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, size}, // Terminal control
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, KeyEventKind, MouseButton, MouseEvent, MouseEventKind}, // For handling keyboard/mouse events
+    execute, queue, // Macros to run terminal commands now, or batched until the next flush
+    style::{Print, Color, SetBackgroundColor, Stylize}, // To print styled or plain text
+    terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen, size}, // Terminal control
 };
-use std::{env, fs, io::{self, Write}, path::Path, result, thread::current, time::{Duration,Instant}}; // Standard IO utilities 
-use std::fs::OpenOptions;
+use unicode_segmentation::UnicodeSegmentation; // Grapheme cluster iteration for cursor movement
+use std::{collections::{HashMap, VecDeque}, env, fs, io::{self, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, process::{Command, Stdio}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}}; // Standard IO utilities 
+
+// How long a status message stays visible
+const STATUS_MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+// Keystrokes of the same kind closer together than this share one undo step
+const UNDO_COALESCE_WINDOW: Duration = Duration::from_millis(700);
+// Most steps kept on each of the undo and redo stacks, the oldest are dropped beyond it
+const MAX_UNDO_DEPTH: usize = 200;
+// How often the pager looks for lines appended to the file while following it
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+// A second Esc within this long cancels everything that is still open
+const DOUBLE_ESCAPE_WINDOW: Duration = Duration::from_millis(400);
 
 // Define a struct `Editor` that holds editor state
 struct Editor {
-    cursor_x: usize, // Cursor's column position
-    cursor_y: usize,  // Cursor's row position
     screen_rows: u16, // Number of rows in the visible screen
     screen_cols: u16, // Number of columns in the visible screen
-    rows: Vec<String>, // Stores lines of text in the editor
-    filename: Option<String>, //Optional filename if its loaded
-    dirty: bool, //tracks whether if file is modified
+    cwd: PathBuf, //canonical directory paths are shown and opened relative to, the start directory until a cd
+    home: Option<PathBuf>, //canonical $HOME, shown as ~
+    path_display: PathDisplay,
     last_key_time: Instant, //Timestamp of last key press
     last_key: Option<KeyEvent>, //last key event, used for debouncing repeated keypresses
-    col_offset: usize, //to check for test more than columns
-    undo_stack: Vec<EditorState>,
-    redo_stack: Vec<EditorState>,
+    last_escape: Option<Instant>, //when Esc was last pressed, a quick second one cancels everything
+    pending_undo: Option<EditorState>, //rows an edit in progress may change, turned into an UndoStep once it is done
     search_mode: bool,
     search_query: String,
-    search_results: Vec<(usize, usize)>, // (row, col)
+    search_results: Vec<(usize, usize, usize)>, // (row, start_col, end_col)
+    search_regex: bool, //treat search_query as a regular expression, toggled with Ctrl+r in the prompt
+    search_error: Option<String>, //why the regex in search_query does not compile
     current_match: usize,
+    search_origin: (usize, usize, usize, usize), //(cursor_x, cursor_y, col_offset, row_offset) when the search started
+    save_as_mode: bool, //true while the save-as filename prompt is open
+    save_as_query: String, //path typed into the save-as prompt
+    save_as_confirm: bool, //the typed path exists, waiting for y to overwrite it
+    status_message: Option<(String, Instant)>, //transient message shown in the status bar and when it was set
+    quit_pending: bool, //set after Alt+q on a dirty buffer, a second Alt+q then quits
+    replace_mode: bool, //true while find-and-replace is driving the search prompt
+    replace_query: String, //text that replaces each match
+    replace_stage: ReplaceStage,
+    clipboard: Vec<String>, //yanked text, one entry per line
+    view_mode: bool, //read-only pager started with --view, editing keys are never dispatched
+    follow: bool, //the pager shows lines as they are appended to the file, like tail -f, toggled with F
+    follow_offset: u64, //bytes of the file read into complete rows while following
+    follow_partial: bool, //the last row is a line the file has not finished yet, read again with what follows it
+    show_line_numbers: bool, //draw the line number gutter, toggled with Alt+Shift+n
+    tab_width: usize, //columns per indentation level
+    scroll_speed: usize, //lines the view moves per scroll wheel notch, set with --scroll-speed=N
+    use_spaces: bool, //Tab inserts tab_width spaces instead of a tab character
+    goto_mode: bool, //true while the go-to-line prompt is open
+    goto_query: String, //line[:col] typed into the go-to-line prompt
+    last_frame: Vec<Vec<u8>>, //each screen line as last drawn, unchanged lines are not repainted
+    highlight_current_line: bool, //draw the cursor's line on the theme's current_line_bg
+    show_trailing_whitespace: bool, //mark spaces and tabs at the end of lines so they are not saved unnoticed
+    theme: Theme, //colors of the syntax, status bar, prompts and emphasis, a copy of themes[theme_index]
+    themes: Vec<(String, Theme)>, //built-in, config and loaded themes by name, cycled with Alt+t
+    theme_index: usize,
+    word_wrap: bool, //continue long lines on the next screen lines instead of scrolling sideways, toggled with Alt+z
+    autosave_interval: Option<Duration>, //how often modified files are saved without asking, from the config
+    last_autosave: Instant,
+    blame_visible: bool, //annotate every visible line with its blame summary, toggled with Alt+Shift+b
+    mouse_capture: bool, //take mouse events from the terminal, off leaves its native selection working, toggled with Alt+m
+    buffers: Vec<Buffer>, //every open file, never empty; the editor derefs to the active one
+    active_buffer: usize, //index in buffers of the file on screen
+    open_mode: bool, //true while the open-file prompt is open
+    open_query: String, //path typed into the open-file prompt
+    cd_mode: bool, //true while the change-directory prompt is open
+    cd_query: String, //directory typed into the change-directory prompt
+    split: Option<Pane>, //view of the pane without focus while the screen is split, toggled with Alt+\
+    focus_top: bool, //the focused pane is the top one, the other is below it
+    key_overrides: Vec<(KeyChord, KeyChord)>, //chords from the config's [keys] and the built-in chord each stands for
+    line_limits: Vec<(Language, Vec<usize>)>, //per file type, the longest each line should be, from the config's [limits]
 }
 
-#[derive(Clone)]
-struct EditorState{
-    buffer: Vec<String>,
+// The editor's per-file fields (self.rows, self.cursor_x, ...) are those of the active buffer
+impl std::ops::Deref for Editor {
+    type Target = Buffer;
+    fn deref(&self) -> &Buffer {
+        &self.buffers[self.active_buffer]
+    }
+}
+
+impl std::ops::DerefMut for Editor {
+    fn deref_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.active_buffer]
+    }
+}
+
+// Where a pane is looking: which buffer, its cursor and scroll position
+struct Pane {
+    buffer: usize,
     cursor_x: usize,
     cursor_y: usize,
+    row_offset: usize,
+    col_offset: usize,
+    desired_x: usize,
+    selection_anchor: Option<(usize, usize)>,
 }
 
-impl Editor {
-    // Constructor: Initializes a new Editor with terminal size and one empty line
-    fn new() -> std::io::Result<Self> {
-        let (cols, rows) = size()?; // Get terminal width and height
-        Ok(Self {
-            cursor_x: 0,
-            cursor_y: 0,
-            screen_rows: rows,
-            screen_cols: cols,
-            rows: vec![String::new()],// Start with one empty line
-            filename: None, 
-            dirty: false,
-            last_key_time: Instant::now(), //Initialize debounce timer
-            last_key: None, //No previous key pressed
-            col_offset: 0,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
-            search_mode: false,
-            search_query: String::new(),
-            search_results: Vec::new(),
-            current_match: 0,
-        })
-    }
+// Per-file state: the text, its undo history, and where the cursor and view are in it
+#[derive(Default)]
+struct Buffer {
+    rows: Vec<String>, // Stores lines of text in the editor
+    filename: Option<String>, //Optional filename if its loaded
+    absolute_path: Option<PathBuf>, //canonical form of filename, for showing it relative to wherever we run
+    dirty: bool, //tracks whether if file is modified
+    readonly: bool, //the file is not writable or --readonly was given, editing keys and Alt+s are refused
+    cursor_x: usize, // Cursor's column position
+    cursor_y: usize,  // Cursor's row position
+    col_offset: usize, //to check for test more than columns
+    row_offset: usize, //to check for text more than screen rows
+    desired_x: usize, //rendered column Up/Down/PageUp/PageDown aim for, kept across shorter lines
+    selection_anchor: Option<(usize, usize)>, //(row, col) of the fixed end of the selection, the cursor is the other end
+    undo_stack: VecDeque<UndoStep>, //oldest step at the front so dropping it past MAX_UNDO_DEPTH is cheap
+    redo_stack: VecDeque<UndoStep>,
+    last_edit: Option<(EditKind, usize, usize, Instant)>, //kind, cursor row/col after it and time of the last coalescable edit
+    language: Language, //file type picked from the extension when the name is set, drives highlighting
+    highlight_state: Vec<LineState>, //per row, the comment or string it starts inside of
+    highlight_valid: usize, //rows at the top of highlight_state no edit has touched since it was computed
+    blame_cache: HashMap<usize, (String, String)>, //per 1-based line, its text when it was blamed and the summary
+    blame_rows: usize, //rows when blame_cache was filled, adding or removing one renumbers the lines after it
+    blame_error: Option<String>, //why git blame failed for this file, reported instead of running git again
+}
 
-    fn open(&mut self, filename: &str)->std::io::Result<()>{ //error if not able to read therefore result used
-        let contents = fs::read_to_string(filename)?; // read entire file to a string
-        self.rows = contents.lines().map(|l| l.to_string()).collect(); //assign line to respective rows
-        self.filename = Some(filename.to_string());
-        self.dirty = false; //file is just opened, no unsaved changes
-        Ok(())
+impl Buffer {
+    // a new buffer with one empty line to type on
+    fn empty() -> Self {
+        Self { rows: vec![String::new()], ..Self::default() }
+    }
+    // the blame summary of a row, unless the row or the line count changed since it was blamed
+    fn cached_blame(&self, row: usize) -> Option<&str> {
+        if self.blame_rows != self.rows.len() {
+            return None;
+        }
+        let (text, info) = self.blame_cache.get(&(row + 1))?;
+        (self.rows.get(row) == Some(text)).then_some(info.as_str())
+    }
+    // keep blamed (1-based line, summary) pairs with the text the lines have now
+    fn cache_blame(&mut self, blamed: Vec<(usize, String)>) {
+        if self.blame_rows != self.rows.len() {
+            self.blame_cache.clear(); // numbered for a different set of lines
+            self.blame_rows = self.rows.len();
+        }
+        for (line, info) in blamed {
+            if let Some(text) = self.rows.get(line - 1) {
+                self.blame_cache.insert(line, (text.clone(), info));
+            }
+        }
+    }
+    // has changes an autosave would write
+    fn autosaves(&self) -> bool {
+        self.dirty && !self.readonly && self.filename.is_some()
     }
 
     fn save(&mut self)-> std::io::Result<()>{
@@ -73,391 +165,3853 @@ impl Editor {
             let data  = self.rows.join("\n"); //join all lines with new line
             fs::write(name, data)?; // Added ? to handle potential errors
             self.dirty = false; //npot dirty as it has been just saved
+            self.blame_cache.clear(); // saved lines may have moved
+            self.blame_error = None;
         }
         Ok(())
     }
+}
+
+// States Esc cancels, one per press, from the first variant down; the status bar names the next one
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Transient {
+    Confirm, // the overwrite question on top of the save-as prompt
+    Prompt, // search, replace, save-as, go-to, open or change directory
+    Selection,
+    PendingQuit, // the first of the two Alt+q that quit with unsaved changes
+}
+
+impl Transient {
+    fn name(self) -> &'static str {
+        match self {
+            Transient::Confirm => "confirm",
+            Transient::Prompt => "prompt",
+            Transient::Selection => "selection",
+            Transient::PendingQuit => "quit",
+        }
+    }
+}
 
-    // Draw all rows of the editor to the terminal
-    fn draw_rows(&self, stdout: &mut io::Stdout) -> std::io::Result<()> {
-        for i in 0..self.screen_rows as usize {
-            execute!(stdout, cursor::MoveTo(0, i as u16))?; // Move to the beginning of each line
-            if i < self.rows.len() {
-                let line = &self.rows[i];
-                execute!(stdout, cursor::MoveTo(0,i as u16))?;
-                let visible = if self.col_offset < line.len() {
-                    &line[self.col_offset..]
-                } else {
-                    ""
-                };
-                let screen_cols = self.screen_cols as usize;
-                let mut display_line = String::new();
+// Edits that can merge into the previous undo step
+#[derive(Clone, Copy, PartialEq)]
+enum EditKind {
+    Insert,
+    Delete,
+}
 
-                if self.col_offset > 0 {
-                    display_line.push('»');
-                    //Make sure we only render more characters
-                    display_line.push_str(&visible.chars().take(screen_cols - 1).collect::<String>());
-                } else {
-                    display_line.push_str(&visible.chars().take(screen_cols).collect::<String>());
+// File types the status bar names and highlighting distinguishes
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    C,
+    Cpp,
+    Markdown,
+    Toml,
+    Json,
+    Shell,
+    GitCommit,
+    #[default]
+    PlainText,
+}
+
+// What highlighting needs to know about a language
+struct Syntax {
+    name: &'static str,
+    extensions: &'static [&'static str], // lowercase, without the dot
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    line_comment: &'static str, // empty when the language has none
+    quotes: &'static [char],
+    multiline_quotes: &'static [char], // quotes whose strings may continue on the next line
+    block_comments: bool, // /* */
+    raw_strings: bool, // Rust's r"..." and r#"..."#, with no escapes inside
+    char_literals: bool, // 'x' is a character, a lone ' (a Rust lifetime) is not a quote
+}
+
+// What the highlighter takes a piece of a line for, the theme gives each its color
+#[derive(Clone, Copy, PartialEq)]
+enum Token {
+    Keyword,
+    TypeName,
+    String,
+    StringEscape,
+    Comment,
+    Number,
+    Normal, // identifiers and punctuation
+}
+
+// What a row starts inside of, carried over from the rows above it
+#[derive(Clone, Copy, PartialEq, Default)]
+enum LineState {
+    #[default]
+    Code,
+    BlockComment,
+    String(char), // closed by this quote
+    RawString(usize), // closed by " and this many #
+}
+
+impl LineState {
+    // index just past whatever closes this state when it is open at chars[i], None when it runs past the line
+    fn closing(self, chars: &[char], i: usize) -> Option<usize> {
+        match self {
+            LineState::Code => Some(i),
+            LineState::BlockComment => (i..chars.len().saturating_sub(1)).find(|&j| chars[j..].starts_with(&['*', '/'])).map(|j| j + 2),
+            LineState::String(quote) => {
+                let mut j = i;
+                while j < chars.len() {
+                    if chars[j] == '\\' {
+                        j += 2; // an escaped quote does not close the string
+                    } else if chars[j] == quote {
+                        return Some(j + 1);
+                    } else {
+                        j += 1;
+                    }
                 }
+                None
+            }
+            LineState::RawString(hashes) => (i..chars.len())
+                .find(|&j| chars[j] == '"' && chars.get(j + 1..j + 1 + hashes).is_some_and(|h| h.iter().all(|&c| c == '#')))
+                .map(|j| j + 1 + hashes),
+        }
+    }
+}
 
-                // Apply syntax highlighting
-                let tokens = self.highlight_line(&display_line);
-                for (token, color) in tokens {
-                    execute!(stdout, Print(token.with(color)))?;
+// Push the text of a string literal, escape sequences (\\n, \\", \\\\ or \\u{XXXX}) in their own color
+fn push_string(result: &mut Vec<(String, Token)>, chars: &[char], escapes: bool) {
+    let mut quoted = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if escapes && chars[i] == '\\' {
+            let start = i;
+            i = (i + 2).min(chars.len());
+            if chars[start + 1..i] == ['u'] && chars.get(i) == Some(&'{') {
+                while i < chars.len() && chars[i - 1] != '}' {
+                    i += 1;
+                }
+            }
+            result.push((std::mem::take(&mut quoted), Token::String));
+            result.push((chars[start..i].iter().collect(), Token::StringEscape));
+            continue;
         }
-            } else {
-                execute!(stdout, Print("~"))?; // Placeholder for unused lines
+        quoted.push(chars[i]);
+        i += 1;
+    }
+    result.push((quoted, Token::String));
+}
+
+// Length and # count of a raw string opener (r", r#", br##" ...) at chars[i]
+fn raw_string_start(chars: &[char], i: usize) -> Option<(usize, usize)> {
+    if i > 0 && (chars[i - 1].is_alphanumeric() || chars[i - 1] == '_') {
+        return None; // the end of an identifier, not a prefix
+    }
+    let mut j = i + usize::from(chars[i] == 'b');
+    if chars.get(j) != Some(&'r') {
+        return None;
+    }
+    j += 1;
+    let hashes = chars[j..].iter().take_while(|&&c| c == '#').count();
+    (chars.get(j + hashes) == Some(&'"')).then_some((j + hashes + 1 - i, hashes))
+}
+
+// Index just past a character literal at chars[i] ('x', '\n', '\u{1F600}'), None for a lifetime like 'a
+fn char_literal_end(chars: &[char], i: usize) -> Option<usize> {
+    if chars[i] != '\'' {
+        return None;
+    }
+    if chars.get(i + 1) == Some(&'\\') {
+        return (i + 3..chars.len().min(i + 13)).find(|&j| chars[j] == '\'').map(|j| j + 1);
+    }
+    (chars.get(i + 2) == Some(&'\'')).then_some(i + 3)
+}
+
+impl Language {
+    // every language, in the order Alt+l cycles through them
+    const ALL: [Language; 12] = [
+        Language::PlainText,
+        Language::Rust,
+        Language::Python,
+        Language::JavaScript,
+        Language::TypeScript,
+        Language::C,
+        Language::Cpp,
+        Language::Markdown,
+        Language::Toml,
+        Language::Json,
+        Language::Shell,
+        Language::GitCommit,
+    ];
+
+    fn from_filename(filename: Option<&str>) -> Self {
+        let base = filename.and_then(|f| Path::new(f).file_name()).and_then(|n| n.to_str());
+        if matches!(base, Some("COMMIT_EDITMSG" | "MERGE_MSG" | "TAG_EDITMSG")) {
+            return Language::GitCommit; // git opening the editor for a message
+        }
+        let extension = filename.and_then(|f| Path::new(f).extension()).and_then(|e| e.to_str());
+        let Some(extension) = extension.map(|e| e.to_ascii_lowercase()) else {
+            return Language::PlainText;
+        };
+        Language::ALL
+            .into_iter()
+            .find(|language| language.syntax().extensions.contains(&extension.as_str()))
+            .unwrap_or(Language::PlainText)
+    }
+
+    fn syntax(self) -> &'static Syntax {
+        const C_KEYWORDS: &[&str] = &[
+            "if", "else", "for", "while", "do", "switch", "case", "default", "break", "continue", "return",
+            "goto", "struct", "union", "enum", "typedef", "static", "extern", "const", "volatile", "sizeof",
+            "inline", "NULL",
+        ];
+        const C_TYPES: &[&str] = &[
+            "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned", "bool",
+            "size_t", "int8_t", "int16_t", "int32_t", "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t",
+        ];
+        const JS_KEYWORDS: &[&str] = &[
+            "function", "return", "if", "else", "for", "while", "do", "switch", "case", "default", "break",
+            "continue", "var", "let", "const", "new", "delete", "typeof", "instanceof", "in", "of", "class",
+            "extends", "super", "this", "import", "export", "from", "as", "async", "await", "yield", "try",
+            "catch", "finally", "throw", "true", "false", "null", "undefined",
+        ];
+        match self {
+            Language::Rust => &Syntax {
+                name: "Rust",
+                extensions: &["rs"],
+                keywords: &[
+                    "fn", "let", "mut", "if", "else", "match", "while", "loop", "for", "in", "return",
+                    "struct", "impl", "enum", "use", "mod", "pub", "crate", "const", "static", "as",
+                    "break", "continue", "trait", "where", "ref", "type",
+                ],
+                types: &["usize", "String", "Result", "Option", "Vec", "i32", "u32", "bool"],
+                line_comment: "//",
+                quotes: &['"'], // ' is a char literal or a lifetime, see char_literals
+                multiline_quotes: &['"'],
+                block_comments: true,
+                raw_strings: true,
+                char_literals: true,
+            },
+            Language::Python => &Syntax {
+                name: "Python",
+                extensions: &["py", "pyw"],
+                keywords: &[
+                    "def", "class", "lambda", "import", "from", "as", "if", "elif", "else", "for", "while",
+                    "with", "return", "yield", "pass", "break", "continue", "try", "except", "finally",
+                    "raise", "and", "or", "not", "in", "is", "True", "False", "None",
+                ],
+                types: &["int", "str", "list", "dict", "tuple", "set", "bool", "float"],
+                line_comment: "#",
+                quotes: &['"', '\''],
+                multiline_quotes: &[],
+                block_comments: false,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::JavaScript => &Syntax {
+                name: "JavaScript",
+                extensions: &["js", "mjs", "cjs", "jsx"],
+                keywords: JS_KEYWORDS,
+                types: &["Array", "Object", "String", "Number", "Boolean", "Promise", "Map", "Set"],
+                line_comment: "//",
+                quotes: &['"', '\'', '`'],
+                multiline_quotes: &['`'],
+                block_comments: true,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::TypeScript => &Syntax {
+                name: "TypeScript",
+                extensions: &["ts", "tsx"],
+                keywords: &[
+                    "function", "return", "if", "else", "for", "while", "do", "switch", "case", "default",
+                    "break", "continue", "var", "let", "const", "new", "typeof", "instanceof", "in", "of",
+                    "class", "extends", "implements", "interface", "type", "enum", "namespace", "super",
+                    "this", "import", "export", "from", "as", "async", "await", "try", "catch", "finally",
+                    "throw", "public", "private", "protected", "readonly", "true", "false", "null", "undefined",
+                ],
+                types: &["string", "number", "boolean", "any", "unknown", "never", "void", "Array", "Promise"],
+                line_comment: "//",
+                quotes: &['"', '\'', '`'],
+                multiline_quotes: &['`'],
+                block_comments: true,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::C => &Syntax {
+                name: "C",
+                extensions: &["c", "h"],
+                keywords: C_KEYWORDS,
+                types: C_TYPES,
+                line_comment: "//",
+                quotes: &['"', '\''],
+                multiline_quotes: &[],
+                block_comments: true,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::Cpp => &Syntax {
+                name: "C++",
+                extensions: &["cpp", "cc", "cxx", "hpp", "hh", "hxx"],
+                keywords: &[
+                    "if", "else", "for", "while", "do", "switch", "case", "default", "break", "continue",
+                    "return", "struct", "union", "enum", "typedef", "static", "extern", "const", "sizeof",
+                    "inline", "class", "namespace", "template", "typename", "public", "private", "protected",
+                    "virtual", "override", "new", "delete", "this", "using", "nullptr", "constexpr", "try",
+                    "catch", "throw", "operator", "true", "false",
+                ],
+                types: &["void", "char", "short", "int", "long", "float", "double", "unsigned", "bool", "auto", "size_t", "string", "vector"],
+                line_comment: "//",
+                quotes: &['"', '\''],
+                multiline_quotes: &[],
+                block_comments: true,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::Markdown => &Syntax {
+                name: "Markdown",
+                extensions: &["md", "markdown"],
+                keywords: &[],
+                types: &[],
+                line_comment: "",
+                quotes: &[],
+                multiline_quotes: &[],
+                block_comments: false,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::Toml => &Syntax {
+                name: "TOML",
+                extensions: &["toml"],
+                keywords: &["true", "false"],
+                types: &[],
+                line_comment: "#",
+                quotes: &['"', '\''],
+                multiline_quotes: &[],
+                block_comments: false,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::Json => &Syntax {
+                name: "JSON",
+                extensions: &["json"],
+                keywords: &["true", "false", "null"],
+                types: &[],
+                line_comment: "",
+                quotes: &['"'],
+                multiline_quotes: &[],
+                block_comments: false,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::Shell => &Syntax {
+                name: "Shell",
+                extensions: &["sh", "bash", "zsh"],
+                keywords: &[
+                    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+                    "in", "function", "return", "local", "export", "readonly", "echo", "exit", "set", "unset",
+                ],
+                types: &[],
+                line_comment: "#",
+                quotes: &['"', '\''],
+                multiline_quotes: &[],
+                block_comments: false,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::GitCommit => &Syntax {
+                name: "Git Commit",
+                extensions: &[],
+                keywords: &[],
+                types: &[],
+                line_comment: "#",
+                quotes: &[],
+                multiline_quotes: &[],
+                block_comments: false,
+                raw_strings: false,
+                char_literals: false,
+            },
+            Language::PlainText => &Syntax {
+                name: "Plain Text",
+                extensions: &[],
+                keywords: &[],
+                types: &[],
+                line_comment: "",
+                quotes: &[],
+                multiline_quotes: &[],
+                block_comments: false,
+                raw_strings: false,
+                char_literals: false,
+            },
+        }
+    }
+
+    fn line_comment(self) -> &'static str {
+        self.syntax().line_comment
+    }
+
+    fn quotes(self) -> &'static [char] {
+        self.syntax().quotes
+    }
+
+    fn name(self) -> &'static str {
+        self.syntax().name
+    }
+
+    // the language a [limits] key names: its name in lowercase with _ for spaces, e.g. git_commit or "c++"
+    fn from_config_name(key: &str) -> Option<Language> {
+        let key = key.trim_matches('"');
+        Language::ALL.into_iter().find(|language| language.name().to_ascii_lowercase().replace(' ', "_") == key)
+    }
+
+    // the language after this one in ALL, wrapping around
+    fn next(self) -> Language {
+        let at = Language::ALL.iter().position(|&l| l == self).unwrap_or(0);
+        Language::ALL[(at + 1) % Language::ALL.len()]
+    }
+}
+
+// Extra emphasis drawn over the syntax color of a run of text
+#[derive(Clone, Copy, PartialEq)]
+enum Emphasis {
+    None,
+    Selection,
+    CurrentMatch,
+    Match,
+    OverLimit, // past the line's length limit
+    Bracket, // bracket under the cursor and its pair
+    TrailingWhitespace,
+}
+
+// Every color the editor draws with, Theme::default() is the built-in "dark" look
+#[derive(Clone, Copy, PartialEq)]
+struct Theme {
+    keyword: Color,
+    type_name: Color,
+    string: Color,
+    string_escape: Color,
+    comment: Color,
+    number: Color,
+    normal: Color, // identifiers and punctuation
+    status_bg: Color,
+    status_fg: Color,
+    prompt_bg: Color,
+    prompt_fg: Color,
+    search_active_bg: Color, // the current match
+    search_inactive_bg: Color, // every other match
+    search_fg: Color,
+    current_line_bg: Color,
+    gutter_fg: Color,
+    gutter_current_fg: Color, // number of the cursor's line
+    gutter_match_fg: Color, // number of a line with search matches
+    bracket_bg: Color,
+    warning_bg: Color, // trailing whitespace and text past a line's length limit
+    selection_bg: Color, // reset draws the selection in inverted colors
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            keyword: Color::Blue,
+            type_name: Color::Cyan,
+            string: Color::Green,
+            string_escape: Color::Yellow,
+            comment: Color::DarkGrey,
+            number: Color::Magenta,
+            normal: Color::Reset,
+            status_bg: Color::DarkGrey,
+            status_fg: Color::White,
+            prompt_bg: Color::Black,
+            prompt_fg: Color::Yellow,
+            search_active_bg: Color::Yellow,
+            search_inactive_bg: Color::DarkYellow,
+            search_fg: Color::Black,
+            current_line_bg: Color::AnsiValue(236), // darker than DarkGrey so comments and line numbers stay readable on it
+            gutter_fg: Color::DarkGrey,
+            gutter_current_fg: Color::Yellow,
+            gutter_match_fg: Color::DarkYellow,
+            bracket_bg: Color::DarkBlue,
+            warning_bg: Color::DarkRed,
+            selection_bg: Color::Reset,
+        }
+    }
+}
+
+impl Theme {
+    // the built-in themes, in the order Alt+t cycles through them
+    fn builtins() -> Vec<(String, Theme)> {
+        vec![("dark".to_string(), Theme::default()), ("light".to_string(), Theme::light())]
+    }
+    // dark variants of the default colors, readable on a white background
+    fn light() -> Theme {
+        Theme {
+            keyword: Color::DarkBlue,
+            type_name: Color::DarkCyan,
+            string: Color::DarkGreen,
+            string_escape: Color::DarkYellow,
+            comment: Color::AnsiValue(244),
+            number: Color::DarkMagenta,
+            normal: Color::Reset,
+            status_bg: Color::AnsiValue(250),
+            status_fg: Color::Black,
+            prompt_bg: Color::AnsiValue(255),
+            prompt_fg: Color::DarkBlue,
+            search_active_bg: Color::AnsiValue(220),
+            search_inactive_bg: Color::AnsiValue(229),
+            search_fg: Color::Black,
+            current_line_bg: Color::AnsiValue(255),
+            gutter_fg: Color::AnsiValue(246),
+            gutter_current_fg: Color::DarkBlue,
+            gutter_match_fg: Color::DarkYellow,
+            bracket_bg: Color::AnsiValue(153),
+            warning_bg: Color::AnsiValue(217),
+            selection_bg: Color::AnsiValue(189),
+        }
+    }
+    // the defaults with the `key = "color"` lines of a theme file on top, e.g. keyword = "#5f87ff"
+    fn from_toml(path: &str) -> io::Result<Theme> {
+        let mut theme = Theme::default();
+        for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+                continue; // comments, and a [theme] header for those who like one
             }
+            let invalid = |what: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, what));
+            let (key, value) = split_setting(line).map_err(invalid)?;
+            theme.set(key, value).map_err(invalid)?;
+        }
+        Ok(theme)
+    }
+    fn color(&self, token: Token) -> Color {
+        match token {
+            Token::Keyword => self.keyword,
+            Token::TypeName => self.type_name,
+            Token::String => self.string,
+            Token::StringEscape => self.string_escape,
+            Token::Comment => self.comment,
+            Token::Number => self.number,
+            Token::Normal => self.normal,
         }
+    }
+    //give the role a theme file key names the color a value names
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let color = parse_color(value).ok_or_else(|| format!("unknown color '{}'", value))?;
+        *self.role_mut(key).ok_or_else(|| format!("unknown key '{}'", key))? = color;
         Ok(())
     }
+    //the color a theme file key sets
+    fn role_mut(&mut self, key: &str) -> Option<&mut Color> {
+        Some(match key {
+            "keyword" => &mut self.keyword,
+            "type_name" => &mut self.type_name,
+            "string" => &mut self.string,
+            "string_escape" => &mut self.string_escape,
+            "comment" => &mut self.comment,
+            "number" => &mut self.number,
+            "normal" => &mut self.normal,
+            "status_bg" => &mut self.status_bg,
+            "status_fg" => &mut self.status_fg,
+            "prompt_bg" => &mut self.prompt_bg,
+            "prompt_fg" => &mut self.prompt_fg,
+            "search_active_bg" => &mut self.search_active_bg,
+            "search_inactive_bg" => &mut self.search_inactive_bg,
+            "search_fg" => &mut self.search_fg,
+            "current_line_bg" => &mut self.current_line_bg,
+            "gutter_fg" => &mut self.gutter_fg,
+            "gutter_current_fg" => &mut self.gutter_current_fg,
+            "gutter_match_fg" => &mut self.gutter_match_fg,
+            "bracket_bg" => &mut self.bracket_bg,
+            "warning_bg" => &mut self.warning_bg,
+            "selection_bg" => &mut self.selection_bg,
+            _ => return None,
+        })
+    }
+}
 
-    // Refresh the entire screen: clear and redraw
-    fn refresh_screen(&self, stdout: &mut io::Stdout) -> std::io::Result<()> {
-        execute!(
-            stdout,
-            cursor::Hide,// Hide cursor during drawing
-            cursor::MoveTo(0, 0), // Move cursor to top-left
-            Clear(ClearType::All) // Clear the entire terminal
-        )?;
-        self.draw_rows(stdout)?;  // Draw current editor content
-        self.draw_status_bar(stdout)?; //draw status bar
-        if self.search_mode {
-            self.draw_search_prompt(stdout)?;
+// `key = value` of a settings line, the value without its quotes or a trailing comment;
+// a comment starts at a # after whitespace, so an unquoted #rrggbb color is still a value
+fn split_setting(line: &str) -> Result<(&str, &str), String> {
+    let (key, value) = line.split_once('=').ok_or("expected key = value")?;
+    let (key, value) = (key.trim(), value.trim());
+    let value = match value.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default(),
+        None => {
+            let comment = value.char_indices().find(|&(at, c)| c == '#' && value[..at].ends_with(char::is_whitespace));
+            value[..comment.map_or(value.len(), |(at, _)| at)].trim_end()
         }
-        // restrict cursor within visible screen
-        let cx = self.cursor_x.saturating_sub(self.col_offset) as u16;
-        let cy = self.cursor_y.min(self.screen_rows as usize - 1) as u16;        
-        execute!(
-            stdout,
-            cursor::MoveTo(cx, cy),// Move cursor to correct position
-            cursor::Show // Show the cursor
-        )?;
-        stdout.flush()?; // Flush all output to terminal
-        Ok(())
+    };
+    if value.is_empty() {
+        return Err(format!("no value for '{}'", key));
     }
+    Ok((key, value))
+}
 
-    // Handle keypress events, return true if 'Alt+q' is pressed to quit
-    fn process_keypress(&mut self, event: KeyEvent) -> bool {
-        if event.kind != KeyEventKind::Press {
-            return false; //handle only actual keypresses and ignore repeats or releases
+// A key with its modifiers, as written in the [keys] section of the config
+type KeyChord = (KeyModifiers, char);
+
+// Preferences read at startup, the defaults are what the editor does without a config file
+// How file paths are shown in the status bar, from the config's path_display
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+enum PathDisplay {
+    #[default]
+    Shortest, // relative to the cwd when that is shorter than the absolute form
+    Absolute, // always from / or ~
+    Relative, // always from the cwd, with .. to get out of it
+}
+
+#[derive(Clone)]
+struct Config {
+    tab_stop: usize,
+    soft_tabs: bool,
+    show_line_numbers: bool,
+    theme: Option<String>, // name of a built-in or [theme.NAME] theme, or a theme file, --theme wins over it
+    themes: Vec<(String, Theme)>, // [theme.NAME] sections, after the built-ins when cycling
+    mouse: bool,
+    scroll_speed: usize,
+    autosave_interval: Option<Duration>, // save modified files this often, None (0 in the file) never does
+    path_display: PathDisplay,
+    keys: Vec<(KeyChord, KeyChord)>, // chord from [keys] and the built-in chord it stands for
+    limits: Vec<(Language, Vec<usize>)>, // line length limits from [limits], see Editor::line_limit
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tab_stop: 4,
+            soft_tabs: true,
+            show_line_numbers: true,
+            theme: None,
+            themes: Vec::new(),
+            mouse: true,
+            scroll_speed: 3,
+            autosave_interval: None,
+            path_display: PathDisplay::Shortest,
+            keys: Vec::new(),
+            limits: vec![(Language::GitCommit, vec![50, 0, 72])], // summary, blank line, body
         }
-        
-        // Simple debouncing: ignore if same key pressed within 50ms
-        let now = Instant::now();
-        if let Some(last_key) = self.last_key {
-            if now.duration_since(self.last_key_time) < Duration::from_millis(50) 
-                && last_key.code == event.code 
-                && last_key.modifiers == event.modifiers {
-                return false;
+    }
+}
+
+impl Config {
+    // ~/.config/rust_editor/config.toml, or under $XDG_CONFIG_HOME when that is set
+    fn user_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config")))?;
+        Some(dir.join("rust_editor").join("config.toml"))
+    }
+    // the user config, then a .rusteditor.toml in the working directory on top, with why any file was skipped
+    fn load() -> (Config, Vec<String>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        for path in [Config::user_path(), Some(PathBuf::from(".rusteditor.toml"))].into_iter().flatten() {
+            if !path.exists() {
+                continue;
+            }
+            // a file with a mistake in it is left out whole rather than half applied
+            let mut next = config.clone();
+            match next.read_file(&path) {
+                Ok(()) => config = next,
+                Err(err) => errors.push(format!("Config {} not loaded: {}", path.display(), err)),
             }
         }
-        
-        self.last_key_time = now;
-        self.last_key = Some(event);
-        match event.code {
-            KeyCode::Char('q') if event.modifiers.contains(KeyModifiers::ALT) => return true, // Quit editor on Alt + q
-            KeyCode::Char('s') if event.modifiers.contains(KeyModifiers::ALT) => {
-                if let Err(e) = self.save() {
-                    eprintln!("Failed to save file: {}", e);
-                }
-                self.dirty = false; // Mark as not dirty after save
+        (config, errors)
+    }
+    // apply the `key = value` lines of a config file, e.g. tab_stop = 2, the chords under [keys], e.g. save = "ctrl+s",
+    // the colors under [theme.NAME], e.g. keyword = "#5f87ff", and the line lengths under [limits], e.g. rust = 100
+    fn read_file(&mut self, path: &Path) -> io::Result<()> {
+        let mut in_keys = false;
+        let mut in_limits = false;
+        let mut in_theme = None; // index in themes of the [theme.NAME] being read
+        for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            KeyCode::Char('z') if event.modifiers.contains(KeyModifiers::CONTROL) => {
-                if let Some(prev) = self.undo_stack.pop() {
-                    self.redo_stack.push(self.snapshot());
-                    self.restore(prev);
+            let invalid = |what: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, what));
+            if let Some(section) = line.strip_prefix('[') {
+                let section = section.trim_end_matches(']').trim();
+                in_keys = section == "keys";
+                in_limits = section == "limits";
+                in_theme = None;
+                if let Some(name) = section.strip_prefix("theme.") {
+                    let name = name.trim_matches('"');
+                    // a later file redefines a theme of the same name from scratch
+                    let theme = (name.to_string(), Theme::default());
+                    in_theme = Some(match self.themes.iter().position(|(other, _)| other == name) {
+                        Some(index) => {
+                            self.themes[index] = theme;
+                            index
+                        }
+                        None => {
+                            self.themes.push(theme);
+                            self.themes.len() - 1
+                        }
+                    });
+                } else if !in_keys && !in_limits {
+                    return Err(invalid(format!("unknown section [{}]", section)));
                 }
+                continue;
             }
-            KeyCode::Char('x') if event.modifiers.contains(KeyModifiers::CONTROL) => {
-                if let Some(next) = self.redo_stack.pop() {
-                    self.undo_stack.push(self.snapshot());
-                    self.restore(next);
-                }
+            let (key, value) = split_setting(line).map_err(invalid)?;
+            if let Some(index) = in_theme {
+                self.themes[index].1.set(key, value).map_err(invalid)?;
+                continue;
             }
-            KeyCode::Char(c) => {
-                self.push_undo();
-                if self.cursor_y < self.rows.len() {
-                    let line = &mut self.rows[self.cursor_y];
-                    if self.cursor_x <= line.len() {
-                        line.insert(self.cursor_x, c);
-                        self.cursor_x += 1;
-                        self.dirty = true; // Mark as dirty when content changes
-                    }
+            if in_limits {
+                let language = Language::from_config_name(key).ok_or_else(|| invalid(format!("unknown file type '{}'", key)))?;
+                self.limits.retain(|&(other, _)| other != language);
+                if value != "none" {
+                    // one length for every line, or one per line with the last for all the rest, e.g. "50, 0, 72"
+                    let limits: Option<Vec<usize>> = value.split(',').map(|limit| limit.trim().parse().ok()).collect();
+                    let limits = limits.ok_or_else(|| invalid(format!("'{}' is not a list of lengths", value)))?;
+                    self.limits.push((language, limits));
                 }
+                continue;
             }
-            KeyCode::Backspace => {
-                self.push_undo();
-                if self.cursor_y < self.rows.len() {
-                    if self.cursor_x > 0 {
-                        let line = &mut self.rows[self.cursor_y];
-                        line.remove(self.cursor_x - 1);
-                        self.cursor_x -= 1;
-                        self.dirty = true; // Mark as dirty
-                    } else if self.cursor_y > 0 {
-                        let current_line = self.rows.remove(self.cursor_y);
-                        self.cursor_y -= 1;
-                        self.cursor_x = self.rows[self.cursor_y].len();
-                        self.rows[self.cursor_y].push_str(&current_line);
-                        self.dirty = true; // Mark as dirty
-                    }
-                }
+            if in_keys {
+                let builtin = match key {
+                    "quit" => (KeyModifiers::ALT, 'q'),
+                    "save" => (KeyModifiers::ALT, 's'),
+                    "search" => (KeyModifiers::ALT, 'f'),
+                    "undo" => (KeyModifiers::CONTROL, 'z'),
+                    "redo" => (KeyModifiers::CONTROL, 'y'),
+                    _ => return Err(invalid(format!("unknown command '{}'", key))),
+                };
+                let chord = parse_chord(value).ok_or_else(|| invalid(format!("'{}' is not a Ctrl or Alt chord", value)))?;
+                self.keys.retain(|&(_, stands_for)| stands_for != builtin); // the project file overrides the user's
+                self.keys.push((chord, builtin));
+                continue;
             }
-            KeyCode::Enter => {
-                self.push_undo();
-                if self.cursor_y < self.rows.len() {
-                    let line = &mut self.rows[self.cursor_y];
-                    let new_line = line.split_off(self.cursor_x);
-                    self.cursor_y += 1;
-                    self.cursor_x = 0;
-                    self.rows.insert(self.cursor_y, new_line);
-                    self.dirty = true; // Mark as dirty
+            let number = || value.parse().ok().filter(|&n| n > 0).ok_or_else(|| invalid(format!("{} must be a positive number", key)));
+            let flag = || value.parse().map_err(|_| invalid(format!("{} must be true or false", key)));
+            match key {
+                "tab_stop" => self.tab_stop = number()?,
+                "soft_tabs" => self.soft_tabs = flag()?,
+                "show_line_numbers" => self.show_line_numbers = flag()?,
+                "mouse" => self.mouse = flag()?,
+                "scroll_speed" => self.scroll_speed = number()?,
+                "autosave_interval" => {
+                    let seconds: u64 = value.parse().map_err(|_| invalid(format!("{} must be a number of seconds, 0 for off", key)))?;
+                    self.autosave_interval = (seconds > 0).then(|| Duration::from_secs(seconds));
                 }
-            }
-            KeyCode::Left => {
-                if self.cursor_x > 0 {
-                    self.cursor_x -= 1;
-                } else if self.cursor_y > 0 {
-                    self.cursor_y -= 1;
-                    self.cursor_x = self.rows[self.cursor_y].len();
+                "path_display" => {
+                    self.path_display = match value {
+                        "shortest" => PathDisplay::Shortest,
+                        "absolute" => PathDisplay::Absolute,
+                        "relative" => PathDisplay::Relative,
+                        _ => return Err(invalid(format!("{} must be shortest, absolute or relative", key))),
+                    };
                 }
-            }
-            KeyCode::Right => {
-                if self.cursor_y < self.rows.len() {
-                    if self.cursor_x < self.rows[self.cursor_y].len() {
-                        self.cursor_x += 1;
-                    } else if self.cursor_y + 1 < self.rows.len() {
-                        self.cursor_y += 1;
-                        self.cursor_x = 0;
-                    }
+                "theme" => {
+                    // a theme file is relative to the config file, not to wherever the editor was started
+                    let file = path.parent().unwrap_or(Path::new(".")).join(value);
+                    self.theme = Some(if file.is_file() { file.to_string_lossy().into_owned() } else { value.to_string() });
                 }
+                _ => return Err(invalid(format!("unknown key '{}'", key))),
             }
-            KeyCode::Up => {
-                if self.cursor_y > 0 {
-                    self.cursor_y -= 1;
-                    self.cursor_x = self.cursor_x.min(self.rows[self.cursor_y].len());
-                }
+        }
+        Ok(())
+    }
+}
+
+// A chord such as alt+q or ctrl+shift+s, a key without Ctrl or Alt is left for typing
+fn parse_chord(value: &str) -> Option<KeyChord> {
+    let mut parts: Vec<&str> = value.split('+').map(str::trim).collect();
+    let mut key = parts.pop()?.chars();
+    let (Some(key), None) = (key.next(), key.next()) else {
+        return None;
+    };
+    let mut modifiers = KeyModifiers::NONE;
+    for part in parts {
+        modifiers |= match part.to_ascii_lowercase().as_str() {
+            "alt" => KeyModifiers::ALT,
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+    }
+    modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT).then_some((modifiers, key.to_ascii_lowercase()))
+}
+
+// Color from a theme file: a name such as dark_grey, an ANSI palette index 0-255, #rrggbb, or reset for the terminal's own
+fn parse_color(value: &str) -> Option<Color> {
+    if value.eq_ignore_ascii_case("reset") {
+        return Some(Color::Reset);
+    }
+    if let Some(hex) = value.strip_prefix('#') {
+        let channel = |at: usize| hex.get(at..at + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        return match (hex.len(), channel(0), channel(2), channel(4)) {
+            (6, Some(r), Some(g), Some(b)) => Some(Color::Rgb { r, g, b }),
+            _ => None,
+        };
+    }
+    if let Ok(index) = value.parse::<u8>() {
+        return Some(Color::AnsiValue(index));
+    }
+    Color::try_from(value).ok()
+}
+
+// Steps of the find-and-replace prompt
+#[derive(Clone, Copy, PartialEq)]
+enum ReplaceStage {
+    Search, // typing the text to find
+    Replacement, // typing the replacement
+    Confirm, // stepping through matches
+}
+
+// Rows first_row.. as they were before an edit, and the cursor then
+struct EditorState{
+    first_row: usize,
+    buffer: Vec<String>,
+    total_rows: usize, // rows in the whole buffer, to see how many the edit added or removed
+    cursor_x: usize,
+    cursor_y: usize,
+}
+
+// One change to the rows; col is a byte offset into the row
+#[derive(PartialEq, Debug)]
+enum EditOp {
+    InsertChar { row: usize, col: usize, ch: char },
+    DeleteChar { row: usize, col: usize, ch: char },
+    SplitLine { row: usize, col: usize },
+    JoinLine { row: usize }, // the row below is appended onto row
+    InsertLine { row: usize, content: String },
+    DeleteLine { row: usize, content: String },
+}
+
+impl EditOp {
+    // make the change, returning the op that takes it back
+    fn apply(self, rows: &mut Vec<String>) -> EditOp {
+        match self {
+            EditOp::InsertChar { row, col, ch } => {
+                rows[row].insert(col, ch);
+                EditOp::DeleteChar { row, col, ch }
             }
-            KeyCode::Down => {
-                if self.cursor_y + 1 < self.rows.len() {
-                    self.cursor_y += 1;
-                    self.cursor_x = self.cursor_x.min(self.rows[self.cursor_y].len());
-                }
+            EditOp::DeleteChar { row, col, ch } => {
+                let removed = rows[row].remove(col);
+                debug_assert_eq!(removed, ch);
+                EditOp::InsertChar { row, col, ch }
+            }
+            EditOp::SplitLine { row, col } => {
+                let tail = rows[row].split_off(col);
+                rows.insert(row + 1, tail);
+                EditOp::JoinLine { row }
+            }
+            EditOp::JoinLine { row } => {
+                let col = rows[row].len();
+                let next = rows.remove(row + 1);
+                rows[row].push_str(&next);
+                EditOp::SplitLine { row, col }
+            }
+            EditOp::InsertLine { row, content } => {
+                rows.insert(row, content.clone());
+                EditOp::DeleteLine { row, content }
+            }
+            EditOp::DeleteLine { row, content } => {
+                let removed = rows.remove(row);
+                debug_assert_eq!(removed, content);
+                EditOp::InsertLine { row, content }
             }
-            
-
-            _ => {}
         }
-        let screen_cols = self.screen_cols as usize;
+    }
+}
 
-        if self.cursor_x < self.col_offset {
-            self.col_offset = self.cursor_x;
-        } else if self.cursor_x >= self.col_offset + screen_cols {
-            self.col_offset = self.cursor_x - screen_cols + 1;
+// The ops that turn the rows `from`, starting at row, into `to`
+fn edit_ops(row: usize, from: &[String], to: &[String]) -> Vec<EditOp> {
+    let mut ops = Vec::new();
+    match (from, to) {
+        ([line], [first, second]) => {
+            let col = common_prefix(line, first);
+            ops.push(EditOp::SplitLine { row, col });
+            char_ops(row, &line[..col], first, &mut ops);
+            char_ops(row + 1, &line[col..], second, &mut ops);
         }
-        false
+        ([first, second], [line]) => {
+            ops.push(EditOp::JoinLine { row });
+            char_ops(row, &format!("{}{}", first, second), line, &mut ops);
+        }
+        _ if from.len() == to.len() => {
+            for (i, (a, b)) in from.iter().zip(to).enumerate() {
+                char_ops(row + i, a, b, &mut ops);
+            }
+        }
+        _ => {
+            // e.g. a paste of several lines, taken out and put in whole
+            ops.extend(from.iter().map(|line| EditOp::DeleteLine { row, content: line.clone() }));
+            ops.extend(to.iter().enumerate().map(|(i, line)| EditOp::InsertLine { row: row + i, content: line.clone() }));
+        }
+    }
+    ops
+}
+
+// The ops that turn row `a` into `b`: the chars between their common start and end go, those of b come in
+fn char_ops(row: usize, a: &str, b: &str, ops: &mut Vec<EditOp>) {
+    let prefix = common_prefix(a, b);
+    let suffix: usize = a[prefix..].chars().rev().zip(b[prefix..].chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    ops.extend(a[prefix..a.len() - suffix].chars().map(|ch| EditOp::DeleteChar { row, col: prefix, ch }));
+    let mut col = prefix;
+    for ch in b[prefix..b.len() - suffix].chars() {
+        ops.push(EditOp::InsertChar { row, col, ch });
+        col += ch.len_utf8();
     }
+}
 
-    fn draw_status_bar(&self, stdout: &mut io::Stdout) -> std::io::Result<()> {
-        use crossterm::style::{SetAttribute, Attribute, SetBackgroundColor, SetForegroundColor, Color};
-        let file_name = self.filename.as_deref().unwrap_or("[No Name]");
-        let status = if self.dirty {"[Modified]"} else {""};
-        let info = format!("{} {}", file_name, status);
+// Bytes at the start of a that b starts with too
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars().zip(b.chars()).take_while(|(x, y)| x == y).map(|(x, _)| x.len_utf8()).sum()
+}
 
-        let pos = format!("Ln {}, Col {}", self.cursor_y+1, self.cursor_x+1);
-        let padding = (self.screen_cols as usize).saturating_sub(info.len()+pos.len());
-        let status_line = format!("{}{}{}", info, " ".repeat(padding), pos);
-        execute!(
-            stdout,
-            cursor::MoveTo(0, self.screen_rows - 1),
-            SetBackgroundColor(Color::DarkGrey),
-            SetForegroundColor(Color::White),
-            SetAttribute(Attribute::Bold),
-            Print(&status_line[..self.screen_cols as usize]),
-            SetAttribute(Attribute::Reset),
-            SetForegroundColor(Color::Reset),
-            SetBackgroundColor(Color::Reset)
-        )?;
+// One undo (or redo) step: the ops that reverse a change and where the cursor was before and after it
+struct UndoStep {
+    ops: Vec<EditOp>,
+    row: usize, // the first row it touches
+    before: (usize, usize), // (cursor_x, cursor_y)
+    after: (usize, usize),
+}
 
-        Ok(())
+impl UndoStep {
+    // apply the ops, returning the step that puts the change back: undoing one gives the redo step and the other way round
+    fn apply(self, rows: &mut Vec<String>) -> UndoStep {
+        let mut ops: Vec<EditOp> = self.ops.into_iter().map(|op| op.apply(rows)).collect();
+        ops.reverse();
+        UndoStep { ops, ..self }
     }
+}
 
-    fn highlight_line(&self, line: &str)-> Vec<(String, Color)>  {
-        let keywords = [
-            "fn", "let", "mut", "if", "else", "match", "while", "loop", "for", "in", "return",
-            "struct", "impl", "enum", "use", "mod", "pub", "crate", "const", "static", "as",
-            "break", "continue", "trait", "where", "ref", "type",
-        ];
-        let types = ["usize", "String", "Result", "Option", "Vec", "i32", "u32", "bool"];
+impl Editor {
+    // Constructor: Initializes a new Editor with terminal size, one empty line and the preferences from the config
+    fn new(config: &Config) -> std::io::Result<Self> {
+        let (cols, rows) = size()?; // Get terminal width and height
+        Ok(Self::with_size(config, cols, rows))
+    }
+
+    // An editor for a screen of cols x rows, without asking the terminal
+    fn with_size(config: &Config, cols: u16, rows: u16) -> Self {
+        Self {
+            screen_rows: rows,
+            screen_cols: cols,
+            cwd: env::current_dir().and_then(fs::canonicalize).unwrap_or_default(),
+            home: env::var_os("HOME").map(|home| fs::canonicalize(&home).unwrap_or(home.into())),
+            path_display: config.path_display,
+            last_key_time: Instant::now(), //Initialize debounce timer
+            last_key: None, //No previous key pressed
+            last_escape: None,
+            pending_undo: None,
+            search_mode: false,
+            search_query: String::new(),
+            search_results: Vec::new(),
+            search_regex: false,
+            search_error: None,
+            current_match: 0,
+            search_origin: (0, 0, 0, 0),
+            save_as_mode: false,
+            save_as_query: String::new(),
+            save_as_confirm: false,
+            status_message: None,
+            quit_pending: false,
+            replace_mode: false,
+            replace_query: String::new(),
+            replace_stage: ReplaceStage::Search,
+            clipboard: Vec::new(),
+            view_mode: false,
+            follow: false,
+            follow_offset: 0,
+            follow_partial: false,
+            show_line_numbers: config.show_line_numbers,
+            tab_width: config.tab_stop,
+            scroll_speed: config.scroll_speed,
+            use_spaces: config.soft_tabs,
+            goto_mode: false,
+            goto_query: String::new(),
+            last_frame: Vec::new(),
+            highlight_current_line: true,
+            show_trailing_whitespace: true,
+            theme: Theme::default(),
+            themes: Theme::builtins().into_iter().chain(config.themes.iter().cloned()).collect(),
+            theme_index: 0,
+            word_wrap: false,
+            autosave_interval: config.autosave_interval,
+            last_autosave: Instant::now(),
+            blame_visible: false,
+            mouse_capture: config.mouse,
+            buffers: vec![Buffer::empty()],
+            active_buffer: 0,
+            open_mode: false,
+            open_query: String::new(),
+            cd_mode: false,
+            cd_query: String::new(),
+            split: None,
+            focus_top: true,
+            key_overrides: config.keys.clone(),
+            line_limits: config.limits.clone(),
+        }
+    }
+
+    // Switch to the theme of that name, or load it from a theme file
+    fn use_theme(&mut self, name: &str) -> io::Result<()> {
+        let index = match self.themes.iter().position(|(other, _)| other == name) {
+            Some(index) => index,
+            None => {
+                let theme = Theme::from_toml(name)?;
+                let stem = Path::new(name).file_stem().map_or(name.into(), |stem| stem.to_string_lossy());
+                self.themes.push((stem.into_owned(), theme));
+                self.themes.len() - 1
+            }
+        };
+        self.theme_index = index;
+        self.theme = self.themes[index].1;
+        Ok(())
+    }
+
+    // The built-in chord a [keys] override stands for, any other key passes through unchanged
+    fn translate_key(&self, event: KeyEvent) -> KeyEvent {
+        let KeyCode::Char(c) = event.code else {
+            return event;
+        };
+        match self.key_overrides.iter().find(|((modifiers, key), _)| *modifiers == event.modifiers && c.eq_ignore_ascii_case(key)) {
+            Some(&(_, (modifiers, key))) => KeyEvent { code: KeyCode::Char(key), modifiers, ..event },
+            None => event,
+        }
+    }
+
+    fn open(&mut self, filename: &str)->std::io::Result<()>{ //error if not able to read therefore result used
+        let contents = fs::read_to_string(filename)?; // read entire file to a string
+        let readonly = fs::metadata(filename)?.permissions().readonly(); // before anything changes, so a failure leaves the buffer as it was
+        self.rows = contents.lines().map(|l| l.to_string()).collect(); //assign line to respective rows
+        self.invalidate_highlight(0);
+        self.set_filename(filename.to_string());
+        self.dirty = false; //file is just opened, no unsaved changes
+        self.readonly = readonly;
+        Ok(())
+    }
+
+    //name the buffer's file, working out what follows from the name
+    fn set_filename(&mut self, filename: String) {
+        let path = Path::new(&filename);
+        // a file that does not exist yet still has a directory that can be resolved
+        let absolute = fs::canonicalize(path).ok().or_else(|| {
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            Some(fs::canonicalize(parent).ok()?.join(path.file_name()?))
+        });
+        self.absolute_path = absolute;
+        self.blame_cache.clear(); // a different file has different history
+        self.blame_error = None;
+        self.readonly = false; // e.g. a read-only file saved as a copy can be edited again
+        self.language = Language::from_filename(Some(&filename));
+        self.invalidate_highlight(0);
+        self.filename = Some(filename);
+    }
+
+    // Draw all rows of the editor into one byte buffer per screen line
+    fn draw_rows(&self) -> std::io::Result<Vec<Vec<u8>>> {
+        // on a bracket with a pair, both get marked
+        let brackets: Vec<(usize, usize)> = if self.cursor_y < self.rows.len() {
+            self.find_matching_bracket(self.cursor_y, self.cursor_x)
+                .map_or(Vec::new(), |pair| vec![(self.cursor_y, self.cursor_x), pair])
+        } else {
+            Vec::new()
+        };
+        let mut frame = Vec::new();
+        let screen_lines = self.screen_lines();
+        for i in 0..self.text_rows() {
+            let mut out = Vec::new();
+            // row of the buffer shown on this screen line and its first grapheme
+            if let Some(&(file_row, first_col)) = screen_lines.get(i) {
+                let line = &self.rows[file_row];
+                let gutter = self.gutter_width();
+                let matches = self.matches_on_row(file_row);
+                let line_bg = (self.highlight_current_line && file_row == self.cursor_y).then_some(self.theme.current_line_bg);
+                if let Some(bg) = line_bg {
+                    execute!(&mut out, SetBackgroundColor(bg))?; // stays set under the tokens until the row ends
+                }
+                if gutter > 0 && self.word_wrap && first_col > 0 {
+                    execute!(&mut out, Print(" ".repeat(gutter)))?; // the number is only on a line's first screen line
+                } else if gutter > 0 {
+                    let number = format!("{:>width$} ", file_row + 1, width = gutter - 1);
+                    // the cursor's line number stands out from the dim ones around it, lines with matches are tinted
+                    let number_color = if file_row == self.cursor_y {
+                        self.theme.gutter_current_fg
+                    } else if !matches.is_empty() {
+                        self.theme.gutter_match_fg
+                    } else {
+                        self.theme.gutter_fg
+                    };
+                    execute!(&mut out, Print(number.with(number_color)))?;
+                }
+                let screen_cols = self.text_cols();
+                let marker = usize::from(!self.word_wrap && self.col_offset > 0); // the » takes the first screen column
+                // Highlight the whole line, so a token cut off by horizontal scrolling keeps its color
+                let start_state = self.highlight_state.get(file_row).copied().unwrap_or_default();
+                let mut spans = Vec::new(); // (first byte, color) of each token
+                let mut at = 0;
+                for (token, kind) in self.highlight_line(line, start_state) {
+                    spans.push((at, self.theme.color(kind)));
+                    at += token.len();
+                }
+                let color_at = |byte: usize| {
+                    let span = spans.partition_point(|&(start, _)| start <= byte);
+                    span.checked_sub(1).map_or(self.theme.normal, |span| spans[span].1)
+                };
+                let selection = self.selection_range();
+                let limit = self.line_limit(file_row);
+                let trailing_from = if self.show_trailing_whitespace {
+                    grapheme_len(line.trim_end_matches([' ', '\t'])) // where the trailing run of whitespace starts
+                } else {
+                    usize::MAX
+                };
+                let mut run = String::new();
+                let mut run_style = None;
+                if marker == 1 {
+                    run.push('»');
+                    run_style = Some((self.theme.normal, Emphasis::None));
+                }
+                // then draw only the graphemes that fit, painting the selection and search matches on top,
+                // a tab is as wide as the gap to the next stop
+                let start_render = render_col(line, first_col, self.tab_width);
+                let mut render_at = start_render;
+                let mut cut = false; // the rest of the line is off screen or on the next screen line
+                for (col, (byte, g)) in line.grapheme_indices(true).enumerate().skip(first_col) {
+                    let width = grapheme_width(g, render_at, self.tab_width);
+                    if render_at + width - start_render + marker > screen_cols {
+                        cut = true;
+                        break;
+                    }
+                    let emphasis = if selection.is_some_and(|(start, end)| (start..end).contains(&(file_row, col))) {
+                        Emphasis::Selection
+                    } else if brackets.contains(&(file_row, col)) {
+                        Emphasis::Bracket
+                    } else {
+                        match matches.iter().find(|(start, end, _)| (*start..*end).contains(&col)) {
+                            Some((_, _, true)) => Emphasis::CurrentMatch,
+                            Some(_) => Emphasis::Match,
+                            None if col >= trailing_from => Emphasis::TrailingWhitespace,
+                            None if limit.is_some_and(|limit| col >= limit) => Emphasis::OverLimit,
+                            None => Emphasis::None,
+                        }
+                    };
+                    let style = (color_at(byte), emphasis);
+                    if let Some(prev) = run_style
+                        && prev != style {
+                        print_styled(&mut out, &run, prev, line_bg, &self.theme)?; // style changed, flush the previous run
+                        run.clear();
+                    }
+                    run_style = Some(style);
+                    if g == "\t" {
+                        run.push_str(&" ".repeat(width)); // expand to the next tab stop, counted from the start of the line
+                    } else {
+                        run.push_str(g);
+                    }
+                    render_at += width;
+                }
+                if let Some(style) = run_style {
+                    print_styled(&mut out, &run, style, line_bg, &self.theme)?;
+                }
+                let mut used = render_at - start_render + marker;
+                if self.blame_visible
+                    && !cut
+                    && let Some(info) = self.cached_blame(file_row) {
+                    // dim, after the end of the line, as much of it as fits
+                    let mut annotation = String::new();
+                    for g in format!("  {}", info).graphemes(true) {
+                        let width = grapheme_width(g, used, self.tab_width);
+                        if used + width > screen_cols {
+                            break;
+                        }
+                        annotation.push_str(g);
+                        used += width;
+                    }
+                    execute!(&mut out, Print(annotation.with(self.theme.comment)))?;
+                }
+                if line_bg.is_some() {
+                    // fill out the row, then keep the background from leaking into the next one
+                    execute!(&mut out, Print(" ".repeat(screen_cols.saturating_sub(used))), SetBackgroundColor(Color::Reset))?;
+                }
+            } else {
+                execute!(&mut out, Print("~"))?; // Placeholder for unused lines
+            }
+            frame.push(out);
+        }
+        Ok(frame)
+    }
+
+    // Redraw the screen, repainting only the lines that changed since the last frame
+    fn refresh_screen(&mut self, stdout: &mut io::Stdout) -> std::io::Result<()> {
+        let mut frame = Vec::new();
+        if self.split.is_some() && !self.focus_top {
+            frame = self.draw_other_pane()?; // the unfocused pane is above
+        }
+        frame.extend(self.draw_rows()?);  // Draw current editor content
+        let mut bottom = Vec::new();
+        let input_prompt = self.input_prompt();
+        if self.search_mode {
+            self.draw_search_prompt(&mut bottom)?;
+        } else if let Some(prompt) = &input_prompt {
+            self.draw_prompt(&mut bottom, prompt)?;
+        } else {
+            self.draw_status_bar(&mut bottom)?; //draw status bar
+        }
+        frame.push(bottom);
+        if self.split.is_some() && self.focus_top {
+            frame.extend(self.draw_other_pane()?);
+        }
+        queue!(stdout, cursor::Hide)?; // Hide cursor during drawing
+        if frame.len() != self.last_frame.len() {
+            queue!(stdout, Clear(ClearType::All))?; // first frame or a resize, nothing on screen to reuse
+            self.last_frame.clear();
+        }
+        // only lines that differ from the previous frame are repainted
+        for (i, line) in frame.iter().enumerate() {
+            if self.last_frame.get(i) != Some(line) {
+                // clear first: after a line that fills the width the terminal would erase its last cell
+                queue!(stdout, cursor::MoveTo(0, i as u16), Clear(ClearType::UntilNewLine))?;
+                stdout.write_all(line)?;
+            }
+        }
+        self.last_frame = frame;
+        // restrict cursor within visible screen, tabs make the rendered column differ from cursor_x
+        let line = self.rows.get(self.cursor_y).map_or("", |l| l.as_str());
+        let marker = usize::from(!self.word_wrap && self.col_offset > 0);
+        let (screen_line, first_col) = self.cursor_screen_line();
+        let render_x = render_col(line, self.cursor_x, self.tab_width)
+            .saturating_sub(render_col(line, first_col, self.tab_width));
+        let (pane_top, pane_height) = self.pane_extent(self.focus_top);
+        // at the end of a wrapped line that fills the width there is no cell right of it
+        let cx_max = (self.screen_cols as usize).saturating_sub(1);
+        let mut cx = (render_x + marker + self.gutter_width()).min(cx_max) as u16;
+        let mut cy = (pane_top + screen_line) as u16;
+        if let Some(prompt) = &input_prompt {
+            // keep the cursor at the end of the typed text while a prompt is open
+            cx = grapheme_len(prompt).min(self.screen_cols.saturating_sub(1) as usize) as u16;
+            cy = (pane_top + pane_height).saturating_sub(1) as u16;
+        }
+        execute!(
+            stdout,
+            cursor::MoveTo(cx, cy),// Move cursor to correct position
+            cursor::Show // Show the cursor
+        )?;
+        stdout.flush()?; // Flush all output to terminal
+        Ok(())
+    }
+
+    //send a key to the prompt that is open, or to the editor; returns true when it should quit
+    fn handle_key(&mut self, event: KeyEvent) -> bool {
+        if event.code == KeyCode::Esc {
+            if event.kind == KeyEventKind::Press {
+                self.escape(); // every mode cancels the same way
+            }
+            return false;
+        }
+        if self.search_mode {
+            self.process_search_keypress(event);
+        } else if self.save_as_mode {
+            self.process_save_as_keypress(event);
+        } else if self.goto_mode {
+            self.process_goto_keypress(event);
+        } else if self.open_mode {
+            self.process_open_keypress(event);
+        } else if self.cd_mode {
+            self.process_cd_keypress(event);
+        } else if self.view_mode {
+            return self.process_view_keypress(event);
+        } else {
+            let event = self.translate_key(event); // config [keys] chords become the ones matched below
+            if event.code == KeyCode::Char('f') && event.modifiers.contains(KeyModifiers::ALT) {
+                self.start_search();
+            } else if matches!(event.code, KeyCode::Char('h') | KeyCode::Char('r')) && event.modifiers.contains(KeyModifiers::ALT) {
+                self.start_replace();
+            } else {
+                return self.process_keypress(event);
+            }
+        }
+        false
+    }
+    fn prompt_open(&self) -> bool {
+        self.search_mode || self.save_as_mode || self.goto_mode || self.open_mode || self.cd_mode
+    }
+    //what is open that Esc can cancel, the one the next Esc cancels first
+    fn cancel_stack(&self) -> Vec<Transient> {
+        [
+            (Transient::Confirm, self.save_as_confirm),
+            (Transient::Prompt, self.prompt_open()),
+            (Transient::Selection, self.selection_anchor.is_some()),
+            (Transient::PendingQuit, self.quit_pending),
+        ]
+        .into_iter()
+        .filter_map(|(transient, active)| active.then_some(transient))
+        .collect()
+    }
+    fn cancel(&mut self, transient: Transient) {
+        match transient {
+            Transient::Confirm => self.save_as_confirm = false, // back to editing the name
+            Transient::Prompt if self.search_mode => {
+                if !self.replace_mode {
+                    // go back to where the search started
+                    (self.cursor_x, self.cursor_y, self.col_offset, self.row_offset) = self.search_origin;
+                }
+                self.end_search();
+            }
+            Transient::Prompt if self.save_as_mode => {
+                self.save_as_mode = false;
+                self.save_as_query.clear();
+                self.set_status_message("Save cancelled");
+            }
+            Transient::Prompt => {
+                // the cursor has not moved yet, so nothing to restore
+                self.goto_mode = false;
+                self.open_mode = false;
+                self.cd_mode = false;
+            }
+            Transient::Selection => self.selection_anchor = None,
+            Transient::PendingQuit => {
+                self.quit_pending = false;
+                self.status_message = None; // the warning asking for a second Alt+q
+            }
+        }
+    }
+    //Esc cancels the top of the stack, a second one in quick succession everything left;
+    //with nothing open it dismisses the status message
+    fn escape(&mut self) {
+        let now = Instant::now();
+        let double = self.last_escape.is_some_and(|at| now.duration_since(at) < DOUBLE_ESCAPE_WINDOW);
+        self.last_escape = if double { None } else { Some(now) }; // a third press starts over
+        let stack = self.cancel_stack();
+        if stack.is_empty() {
+            self.status_message = None;
+        }
+        let count = if double { stack.len() } else { 1 };
+        for transient in stack.into_iter().take(count) {
+            self.cancel(transient);
+        }
+    }
+    fn process_keypress(&mut self, event: KeyEvent) -> bool {
+        if event.kind != KeyEventKind::Press {
+            return false; //handle only actual keypresses and ignore repeats or releases
+        }
+        
+        // Simple debouncing: ignore if same key pressed within 50ms
+        let now = Instant::now();
+        if let Some(last_key) = self.last_key
+            && now.duration_since(self.last_key_time) < Duration::from_millis(50)
+            && last_key.code == event.code
+            && last_key.modifiers == event.modifiers {
+            return false;
+        }
         
+        self.last_key_time = now;
+        self.last_key = Some(event);
+        let quit_pending = std::mem::take(&mut self.quit_pending); // any key other than a second Alt+q cancels the pending quit
+        let navigation = matches!(
+            event.code,
+            KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down
+                | KeyCode::Home | KeyCode::End | KeyCode::PageUp | KeyCode::PageDown
+        );
+        if navigation {
+            if !event.modifiers.contains(KeyModifiers::SHIFT) {
+                self.selection_anchor = None; // plain movement drops the selection
+            } else if self.selection_anchor.is_none() {
+                self.selection_anchor = Some((self.cursor_y, self.cursor_x)); // Shift+movement starts one here
+            }
+        }
+        if self.readonly && edits_buffer(&event) {
+            self.set_status_message("Read-only file, Alt+Shift+s saves a copy that can be edited");
+            return false;
+        }
+        match event.code {
+            KeyCode::Char('q') if event.modifiers.contains(KeyModifiers::ALT) => {
+                let dirty_buffers = self.dirty_buffer_count();
+                if dirty_buffers > 0 && !quit_pending {
+                    // Ask for confirmation before throwing away unsaved changes
+                    self.quit_pending = true;
+                    if dirty_buffers == 1 && self.dirty {
+                        self.set_status_message("File has unsaved changes. Press Alt+Q again to quit, Alt+S to save");
+                    } else {
+                        let plural = if dirty_buffers == 1 { "" } else { "s" };
+                        self.set_status_message(format!("Unsaved changes in {} buffer{}. Press Alt+Q again to quit", dirty_buffers, plural));
+                    }
+                    return false;
+                }
+                return true; // Quit editor on Alt + q
+            }
+            KeyCode::Char('S') | KeyCode::Char('s')
+                if event.modifiers.contains(KeyModifiers::ALT) && event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.start_save_as(); // Alt+Shift+s always asks for a new name
+            }
+            KeyCode::Char('s') if event.modifiers.contains(KeyModifiers::ALT) => {
+                if self.readonly {
+                    self.set_status_message("Cannot save: read-only file");
+                } else if self.filename.is_none() {
+                    self.start_save_as(); // nothing to write to yet, ask for a name
+                } else {
+                    self.save_and_report();
+                }
+            }
+            KeyCode::Char('z') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.finish_undo_step();
+                if let Some(step) = self.undo_stack.pop_back() {
+                    let step = step.apply(&mut self.rows);
+                    self.invalidate_highlight(step.row);
+                    self.restore_cursor(step.before);
+                    push_capped(&mut self.redo_stack, step);
+                }
+            }
+            KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                if self.selection_range().is_some() {
+                    self.copy_to_clipboard(self.selected_text());
+                } else {
+                    self.set_status_message("Nothing selected to copy");
+                }
+            }
+            KeyCode::Char('x') if event.modifiers.contains(KeyModifiers::CONTROL) && self.selection_range().is_some() => {
+                // cut the selection, without one Ctrl+x stays redo
+                self.push_undo_rows(self.cursor_y, 1);
+                self.copy_to_clipboard(self.selected_text());
+                self.delete_selection();
+            }
+            KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::ALT) => {
+                let lines = self.selection_or_line();
+                self.copy_to_clipboard(lines);
+            }
+            KeyCode::Char('x') if event.modifiers.contains(KeyModifiers::ALT) => {
+                self.push_undo_rows(self.cursor_y, 1);
+                let lines = self.selection_or_line();
+                self.copy_to_clipboard(lines);
+                if !self.delete_selection() && self.cursor_y < self.rows.len() {
+                    self.remove_line(); // no selection, the whole line goes
+                    self.cursor_x = 0;
+                }
+            }
+            KeyCode::Char('v') | KeyCode::Char('V')
+                if event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                // Ctrl+Shift+v always asks the system clipboard
+                let text = self.clipboard_for_paste(event.modifiers.contains(KeyModifiers::SHIFT));
+                if text.is_empty() {
+                    self.set_status_message("Clipboard is empty");
+                } else {
+                    self.push_undo_rows(self.cursor_y, 1); // pasted lines are inserted after it
+                    self.delete_selection(); // pasting replaces the selection
+                    self.insert_text(&text);
+                }
+            }
+            KeyCode::Char('x') | KeyCode::Char('y') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.finish_undo_step();
+                if let Some(step) = self.redo_stack.pop_back() {
+                    let step = step.apply(&mut self.rows);
+                    self.invalidate_highlight(step.row);
+                    self.restore_cursor(step.after);
+                    push_capped(&mut self.undo_stack, step);
+                }
+            }
+            KeyCode::Char('d') | KeyCode::Char('D')
+                if (event.modifiers.contains(KeyModifiers::CONTROL)
+                    || event.modifiers.contains(KeyModifiers::ALT | KeyModifiers::SHIFT))
+                    && self.cursor_y < self.rows.len() => {
+                // Duplicate the current line below it, the cursor follows onto the copy
+                let row = self.cursor_y;
+                self.push_undo_rows(row, 1);
+                let line = self.rows[row].clone();
+                self.rows.insert(row + 1, line);
+                self.cursor_y += 1;
+                self.dirty = true;
+            }
+            KeyCode::Char('k') if event.modifiers.contains(KeyModifiers::CONTROL) && self.cursor_y < self.rows.len() => {
+                // Kill the current line into the clipboard, Ctrl+v puts it back
+                self.push_undo_rows(self.cursor_y, 1);
+                let line = self.remove_line();
+                self.copy_to_clipboard(vec![line]);
+            }
+            KeyCode::Char('d') if event.modifiers.contains(KeyModifiers::ALT) && self.cursor_y < self.rows.len() => {
+                // Delete the current line, leaving the clipboard alone
+                self.push_undo_rows(self.cursor_y, 1);
+                self.remove_line();
+            }
+            KeyCode::Char('g') if event.modifiers.contains(KeyModifiers::ALT) => self.start_goto(),
+            KeyCode::Char('B') | KeyCode::Char('b')
+                if event.modifiers.contains(KeyModifiers::ALT) && event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.blame_visible = !self.blame_visible;
+                let state = if self.blame_visible { "shown on every line, Alt+Shift+b to hide" } else { "hidden" };
+                self.set_status_message(format!("Blame {}", state));
+            }
+            KeyCode::Char('b') if event.modifiers.contains(KeyModifiers::ALT) => self.blame_line(),
+            KeyCode::Char('z') if event.modifiers.contains(KeyModifiers::ALT) => {
+                self.word_wrap = !self.word_wrap;
+                self.col_offset = 0; // wrapped lines always start at their first column
+                self.set_status_message(format!("Word wrap {}", if self.word_wrap {"on"} else {"off"}));
+            }
+            KeyCode::Char('l') if event.modifiers.contains(KeyModifiers::ALT) => {
+                // override detection, e.g. for a Makefile or a script without an extension
+                self.language = self.language.next();
+                self.invalidate_highlight(0);
+                self.set_status_message(format!("Language: {}", self.language.name()));
+            }
+            KeyCode::Char('\\') if event.modifiers.contains(KeyModifiers::ALT) => self.toggle_split(),
+            KeyCode::Char('w') if event.modifiers.contains(KeyModifiers::ALT) => {
+                if self.split.is_some() {
+                    self.focus_other_pane();
+                } else {
+                    self.set_status_message("Screen is not split, Alt+\\ splits it");
+                }
+            }
+            KeyCode::Char('N') | KeyCode::Char('n')
+                if event.modifiers.contains(KeyModifiers::ALT) && event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.show_line_numbers = !self.show_line_numbers;
+            }
+            KeyCode::Char('n') if event.modifiers.contains(KeyModifiers::ALT) => {
+                self.new_buffer();
+                self.set_status_message("New buffer, Alt+s names and saves it");
+            }
+            KeyCode::Char(']') if event.modifiers.contains(KeyModifiers::ALT) => self.cycle_buffer(true),
+            KeyCode::Char('[') if event.modifiers.contains(KeyModifiers::ALT) => self.cycle_buffer(false),
+            KeyCode::Char('O') | KeyCode::Char('o')
+                if event.modifiers.contains(KeyModifiers::ALT) && event.modifiers.contains(KeyModifiers::SHIFT) => {
+                self.cd_mode = true; // Alt+Shift+o changes where Alt+o opens from
+                self.cd_query.clear();
+            }
+            KeyCode::Char('o') if event.modifiers.contains(KeyModifiers::ALT) => {
+                self.open_mode = true;
+                self.open_query.clear();
+            }
+            KeyCode::Char('m') if event.modifiers.contains(KeyModifiers::ALT) => {
+                self.mouse_capture = !self.mouse_capture;
+                let state = if self.mouse_capture { "on, Alt+m again to use terminal selection" } else { "off, Alt+m to turn it back on" };
+                self.set_status_message(format!("Mouse {}", state));
+            }
+            KeyCode::Char(',') if event.modifiers.contains(KeyModifiers::ALT) => self.open_config(),
+            KeyCode::Char('t') if event.modifiers.contains(KeyModifiers::ALT) => {
+                self.theme_index = (self.theme_index + 1) % self.themes.len();
+                self.theme = self.themes[self.theme_index].1;
+                self.set_status_message(format!("Theme: {}", self.themes[self.theme_index].0));
+            }
+            KeyCode::Char('e') if event.modifiers.contains(KeyModifiers::ALT) => self.evaluate_expression(false),
+            KeyCode::Char('=') if event.modifiers.contains(KeyModifiers::ALT) => self.evaluate_expression(true),
+            KeyCode::Char(c)
+                if event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+                    && !event.modifiers.contains(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                // Ctrl/Alt chord with no binding (AltGr arrives as Ctrl+Alt and is still typed as text)
+                let chord = if event.modifiers.contains(KeyModifiers::CONTROL) { "Ctrl" } else { "Alt" };
+                self.set_status_message(format!("Unknown key: {}+{}", chord, c));
+            }
+            KeyCode::Char(c) => {
+                // a burst of typing is one undo step, each new word starts another
+                self.push_undo_coalesced(EditKind::Insert, c.is_whitespace() || self.selection_range().is_some());
+                self.delete_selection(); // typed text replaces the selection
+                if self.cursor_y < self.rows.len() {
+                    let (cursor_x, cursor_y) = (self.cursor_x, self.cursor_y);
+                    let line = &mut self.rows[cursor_y];
+                    if cursor_x <= grapheme_len(line) {
+                        let at = byte_index_of(line, cursor_x);
+                        line.insert(at, c);
+                        // a combining char joins the previous grapheme, so recount instead of adding one
+                        self.cursor_x = grapheme_len(&line[..at + c.len_utf8()]);
+                        self.dirty = true; // Mark as dirty when content changes
+                        self.note_edit(EditKind::Insert);
+                    }
+                }
+            }
+            KeyCode::Backspace if event.modifiers.contains(KeyModifiers::CONTROL) && self.cursor_x > 0 => {
+                // Delete back to the start of the word, at column 0 it joins lines like plain Backspace
+                self.push_undo_rows(self.cursor_y, 1);
+                if !self.delete_selection() && self.cursor_y < self.rows.len() {
+                    let graphemes: Vec<&str> = self.rows[self.cursor_y].graphemes(true).collect();
+                    let start = prev_word_start(&graphemes, self.cursor_x.min(graphemes.len()));
+                    let end = self.cursor_x;
+                    self.delete_columns(start, end);
+                    self.cursor_x = start;
+                }
+            }
+            KeyCode::Backspace => {
+                // deleting characters within a line coalesces, removing a selection or joining lines does not
+                let coalesce = self.selection_range().is_none() && self.cursor_x > 0;
+                self.push_undo_coalesced(EditKind::Delete, !coalesce);
+                if self.delete_selection() {
+                    // the selection was the thing to delete
+                } else if self.cursor_y < self.rows.len() {
+                    let line = &self.rows[self.cursor_y];
+                    let before = &line[..byte_index_of(line, self.cursor_x)];
+                    if self.use_spaces && !before.is_empty() && before.bytes().all(|b| b == b' ') {
+                        // in soft-tab indentation, go back to the previous tab stop
+                        let stop = (self.cursor_x - 1) / self.tab_width * self.tab_width;
+                        self.delete_columns(stop, self.cursor_x);
+                        self.cursor_x = stop;
+                        self.note_edit(EditKind::Delete);
+                    } else if self.cursor_x > 0 {
+                        let (cursor_x, cursor_y) = (self.cursor_x, self.cursor_y);
+                        let line = &mut self.rows[cursor_y];
+                        let start = byte_index_of(line, cursor_x - 1);
+                        let end = byte_index_of(line, cursor_x);
+                        line.replace_range(start..end, ""); // remove the whole grapheme cluster
+                        self.cursor_x -= 1;
+                        self.dirty = true; // Mark as dirty
+                        self.note_edit(EditKind::Delete);
+                    } else if self.cursor_y > 0 {
+                        self.cursor_y -= 1;
+                        self.cursor_x = grapheme_len(&self.rows[self.cursor_y]);
+                        self.join_next_line(self.cursor_y);
+                    }
+                }
+            }
+            KeyCode::Delete if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.push_undo_rows(self.cursor_y, 2); // may join the line below
+                if !self.delete_selection() && self.cursor_y < self.rows.len() {
+                    if self.cursor_x < grapheme_len(&self.rows[self.cursor_y]) {
+                        let graphemes: Vec<&str> = self.rows[self.cursor_y].graphemes(true).collect();
+                        let end = next_word_end(&graphemes, self.cursor_x);
+                        self.delete_columns(self.cursor_x, end);
+                    } else {
+                        self.join_next_line(self.cursor_y);
+                    }
+                }
+            }
+            KeyCode::Delete => {
+                self.push_undo_rows(self.cursor_y, 2); // may join the line below
+                if self.delete_selection() {
+                    // the selection was the thing to delete
+                } else if self.cursor_y < self.rows.len() {
+                    if self.cursor_x < grapheme_len(&self.rows[self.cursor_y]) {
+                        let (cursor_x, cursor_y) = (self.cursor_x, self.cursor_y);
+                        let line = &mut self.rows[cursor_y];
+                        let start = byte_index_of(line, cursor_x);
+                        let end = byte_index_of(line, cursor_x + 1);
+                        line.replace_range(start..end, ""); // remove the grapheme under the cursor
+                        self.dirty = true;
+                    } else {
+                        self.join_next_line(self.cursor_y); // at the end of a line, join the next one
+                    }
+                }
+            }
+            KeyCode::Enter => {
+                self.push_undo_rows(self.cursor_y, 1);
+                self.delete_selection();
+                if self.cursor_y < self.rows.len() {
+                    let (tab_width, cursor_x, cursor_y) = (self.tab_width, self.cursor_x, self.cursor_y);
+                    let line = &mut self.rows[cursor_y];
+                    let at = byte_index_of(line, cursor_x);
+                    // the new line starts with the indentation of the one being split
+                    let mut indent = leading_whitespace(&line[..at]).to_string();
+                    if line[..at].ends_with('{') {
+                        // opening a block, indent one more level in the same style
+                        if indent.contains('\t') { indent.push('\t') } else { indent.push_str(&" ".repeat(tab_width)) }
+                    }
+                    let whitespace_only = line.trim().is_empty();
+                    let new_line = format!("{}{}", indent, line.split_off(at));
+                    if whitespace_only {
+                        line.clear(); // move the indent down instead of leaving it behind on a blank line
+                    }
+                    self.cursor_y += 1;
+                    self.cursor_x = grapheme_len(&indent);
+                    self.rows.insert(cursor_y + 1, new_line);
+                    self.dirty = true; // Mark as dirty
+                }
+            }
+            KeyCode::Tab if event.modifiers.contains(KeyModifiers::ALT) => self.cycle_buffer(true),
+            KeyCode::BackTab if event.modifiers.contains(KeyModifiers::ALT) => self.cycle_buffer(false),
+            KeyCode::Tab => {
+                self.push_undo_rows(self.cursor_y, 1);
+                let indent = if self.use_spaces { " ".repeat(self.tab_width) } else { "\t".to_string() };
+                match self.selection_range() {
+                    Some(((start_y, _), (end_y, end_x))) if end_y > start_y => {
+                        // indent every selected line, keeping the selection; a line selected only up to column 0 is left out
+                        let last = if end_x == 0 { end_y - 1 } else { end_y };
+                        let added = grapheme_len(&indent);
+                        for row in start_y..=last {
+                            self.rows[row].insert_str(0, &indent);
+                            if row == self.cursor_y {
+                                self.cursor_x += added;
+                            }
+                            if let Some((anchor_y, anchor_x)) = self.selection_anchor
+                                && anchor_y == row {
+                                self.selection_anchor = Some((anchor_y, anchor_x + added));
+                            }
+                        }
+                        self.dirty = true;
+                    }
+                    _ => {
+                        self.delete_selection();
+                        self.insert_text(&[indent]);
+                    }
+                }
+            }
+            KeyCode::BackTab => {
+                self.push_undo_rows(self.cursor_y, 1);
+                // unindent every selected line, or just the cursor's line
+                let (first, last) = match self.selection_range() {
+                    Some(((start_y, _), (end_y, _))) => (start_y, end_y),
+                    None => (self.cursor_y, self.cursor_y),
+                };
+                for row in first..=last.min(self.rows.len().saturating_sub(1)) {
+                    let removed = self.unindent_line(row);
+                    if row == self.cursor_y {
+                        self.cursor_x = self.cursor_x.saturating_sub(removed);
+                    }
+                    if let Some((anchor_y, anchor_x)) = self.selection_anchor
+                        && anchor_y == row {
+                        self.selection_anchor = Some((anchor_y, anchor_x.saturating_sub(removed)));
+                    }
+                }
+            }
+            KeyCode::Right if event.modifiers.contains(KeyModifiers::ALT) => self.cycle_buffer(true),
+            KeyCode::Left if event.modifiers.contains(KeyModifiers::ALT) => self.cycle_buffer(false),
+            KeyCode::Left if event.modifiers.contains(KeyModifiers::CONTROL) => self.move_word_left(),
+            KeyCode::Right if event.modifiers.contains(KeyModifiers::CONTROL) => self.move_word_right(),
+            KeyCode::Left => {
+                if self.cursor_x > 0 {
+                    self.cursor_x -= 1;
+                } else if self.cursor_y > 0 {
+                    self.cursor_y -= 1;
+                    self.cursor_x = grapheme_len(&self.rows[self.cursor_y]);
+                }
+            }
+            KeyCode::Right if self.cursor_y < self.rows.len() => {
+                if self.cursor_x < grapheme_len(&self.rows[self.cursor_y]) {
+                    self.cursor_x += 1;
+                } else if self.cursor_y + 1 < self.rows.len() {
+                    self.cursor_y += 1;
+                    self.cursor_x = 0;
+                }
+            }
+            KeyCode::Up if event.modifiers.contains(KeyModifiers::ALT) => self.jump_in_outline(false),
+            KeyCode::Down if event.modifiers.contains(KeyModifiers::ALT) => self.jump_in_outline(true),
+            KeyCode::Up if self.word_wrap => self.move_screen_line(false),
+            KeyCode::Down if self.word_wrap => self.move_screen_line(true),
+            KeyCode::Up if self.cursor_y > 0 => {
+                self.cursor_y -= 1;
+                self.column_from_desired();
+            }
+            KeyCode::Down if self.cursor_y + 1 < self.rows.len() => {
+                self.cursor_y += 1;
+                self.column_from_desired();
+            }
+            KeyCode::Home if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Jump to the top of the file
+                self.cursor_y = 0;
+                self.cursor_x = 0;
+            }
+            KeyCode::End if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Jump to the last character of the last line
+                self.cursor_y = self.rows.len().saturating_sub(1);
+                self.cursor_x = self.rows.get(self.cursor_y).map_or(0, |line| grapheme_len(line));
+            }
+            KeyCode::PageUp => {
+                // Move the cursor and the view up by a full screen together
+                let page = self.text_rows();
+                self.cursor_y = self.cursor_y.saturating_sub(page);
+                self.row_offset = self.row_offset.saturating_sub(page);
+                self.column_from_desired();
+            }
+            KeyCode::PageDown => {
+                let page = self.text_rows();
+                let last_row = self.rows.len().saturating_sub(1);
+                self.cursor_y = (self.cursor_y + page).min(last_row);
+                self.row_offset = (self.row_offset + page).min(last_row);
+                self.column_from_desired();
+            }
+            KeyCode::Home => {
+                self.cursor_x = 0;
+            }
+            KeyCode::End if self.cursor_y < self.rows.len() => {
+                self.cursor_x = grapheme_len(&self.rows[self.cursor_y]);
+            }
+            
+
+            _ => {}
+        }
+        if !matches!(event.code, KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown) {
+            self.remember_column(); // anything but vertical movement sets a new goal column
+        }
+        self.scroll_to_cursor();
+        false
+    }
+
+    fn draw_status_bar(&self, out: &mut Vec<u8>) -> std::io::Result<()> {
+        use crossterm::style::{SetAttribute, Attribute, SetBackgroundColor, SetForegroundColor, Color};
+        let file_name = match (&self.absolute_path, &self.filename) {
+            (Some(absolute), _) => display_path(absolute, &self.cwd, self.home.as_deref(), self.path_display),
+            (None, Some(name)) => name.clone(),
+            (None, None) => "[No Name]".to_string(),
+        };
+        let mut status = if self.follow {"[View] [Follow]"} else if self.view_mode {"[View]"} else if self.readonly {"[READ ONLY]"} else if self.dirty {"[Modified]"} else {""}.to_string();
+        if self.word_wrap {
+            status = format!("{} [WRAP]", status).trim_start().to_string();
+        }
+        // with several files open, which one of them this is
+        let position = if self.buffers.len() > 1 {
+            format!("[{}/{}] ", self.active_buffer + 1, self.buffers.len())
+        } else {
+            String::new()
+        };
+        let info = match &self.status_message {
+            Some((msg, time)) if time.elapsed() < STATUS_MESSAGE_TIMEOUT => msg.clone(),
+            _ => format!("{}{} {}", position, file_name, status),
+        };
+
+        let mut pos = format!("Ln {}, Col {}", self.cursor_y+1, self.cursor_x+1);
+        if let Some(limit) = self.line_limit(self.cursor_y) {
+            // live length of a line that has a limit, e.g. a commit summary
+            let len = self.rows.get(self.cursor_y).map_or(0, |l| grapheme_len(l));
+            pos = format!("{}/{}  {}", len, limit, pos);
+        }
+        if let Some(next) = self.cancel_stack().first() {
+            pos = format!("Esc: {}  {}", next.name(), pos); // what the next Esc cancels
+        }
+        let cols = self.screen_cols as usize;
+        let (info_len, pos_len) = (grapheme_len(&info), grapheme_len(&pos));
+        // the language sits centered between the two, dropped when it would touch either side
+        let language = self.detect_language();
+        let center_at = cols.saturating_sub(language.len()) / 2;
+        let middle = if center_at > info_len && center_at + language.len() < cols.saturating_sub(pos_len) {
+            format!("{}{}", " ".repeat(center_at - info_len), language)
+        } else {
+            String::new()
+        };
+        let padding = cols.saturating_sub(info_len + grapheme_len(&middle) + pos_len);
+        let status_line: String = format!("{}{}{}{}", info, middle, " ".repeat(padding), pos)
+            .chars()
+            .take(cols)
+            .collect();
+        execute!(
+            out,
+            SetBackgroundColor(self.theme.status_bg),
+            SetForegroundColor(self.theme.status_fg),
+            SetAttribute(Attribute::Bold),
+            Print(&status_line),
+            SetAttribute(Attribute::Reset),
+            SetForegroundColor(Color::Reset),
+            SetBackgroundColor(Color::Reset)
+        )?;
+
+        Ok(())
+    }
+
+    //length limit of a row for the file type, the limit list's last entry covers every row past it;
+    //commit messages by default: 50 for the summary, a blank second line, 72 after
+    fn line_limit(&self, row: usize) -> Option<usize> {
+        if self.language == Language::GitCommit && self.rows.get(row).is_some_and(|l| l.starts_with('#')) {
+            return None; // git strips the comment lines
+        }
+        let (_, limits) = self.line_limits.iter().find(|(language, _)| *language == self.language)?;
+        limits.get(row).or(limits.last()).copied()
+    }
+    //display name of the file type detected from the extension when the filename was set
+    fn detect_language(&self) -> &'static str {
+        self.language.name()
+    }
+
+    //columns of the brackets on a row that are code, not inside a string or comment
+    fn bracket_positions(&self, row: usize) -> Vec<(usize, char)> {
+        let line = &self.rows[row];
+        let start = self.highlight_state.get(row).copied().unwrap_or_default();
+        let grapheme_starts: Vec<usize> = line.grapheme_indices(true).map(|(at, _)| at).collect();
+        let mut brackets = Vec::new();
+        let mut at = 0;
+        for (token, kind) in self.highlight_line(line, start) {
+            for (offset, c) in token.char_indices() {
+                if kind == Token::Normal
+                    && matches!(c, '(' | ')' | '[' | ']' | '{' | '}')
+                    && let Ok(col) = grapheme_starts.binary_search(&(at + offset))
+                {
+                    brackets.push((col, c));
+                }
+            }
+            at += token.len();
+        }
+        brackets
+    }
+
+    fn find_matching_bracket(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+        const MAX_DEPTH: usize = 1000;
+        const MAX_CHARS: usize = 50_000; // give up rather than scan a huge mismatched file on every redraw
+        let positions = self.bracket_positions(row);
+        let &(_, bracket) = positions.iter().find(|(c, _)| *c == col)?;
+        let (open, close, forward) = match bracket {
+            '(' => ('(', ')', true),
+            '[' => ('[', ']', true),
+            '{' => ('{', '}', true),
+            ')' => ('(', ')', false),
+            ']' => ('[', ']', false),
+            _ => ('{', '}', false),
+        };
+        let (same, other) = if forward { (open, close) } else { (close, open) };
+        let mut depth = 0;
+        let mut scanned = 0;
+        let mut y = row;
+        let mut line = positions;
+        loop {
+            scanned += self.rows[y].len();
+            // walk this row's brackets away from the cursor
+            let candidates: Vec<(usize, char)> = if forward {
+                line.into_iter().filter(|&(c, _)| y != row || c > col).collect()
+            } else {
+                line.into_iter().filter(|&(c, _)| y != row || c < col).rev().collect()
+            };
+            for (c, ch) in candidates {
+                if ch == same {
+                    depth += 1;
+                    if depth > MAX_DEPTH {
+                        return None;
+                    }
+                } else if ch == other {
+                    if depth == 0 {
+                        return Some((y, c));
+                    }
+                    depth -= 1;
+                }
+            }
+            if scanned > MAX_CHARS {
+                return None;
+            }
+            y = if forward { y + 1 } else { y.checked_sub(1)? };
+            if y >= self.rows.len() {
+                return None;
+            }
+            line = self.bracket_positions(y);
+        }
+    }
+    //bring highlight_state up to date, from the first row an edit may have changed down to the end,
+    //or only until the rows below an edit in progress, once one of them starts in the state it had before
+    fn recompute_highlight_state(&mut self) {
+        let computed = self.highlight_state.len();
+        let mut from = self.highlight_valid.min(computed).min(self.rows.len());
+        // rows from here down are the ones computed last time, moved by the rows the edit added or removed
+        let mut untouched = usize::MAX;
+        if let Some(pending) = &self.pending_undo {
+            from = from.min(pending.first_row); // an edit still in progress, e.g. a burst of typing
+            if self.highlight_valid >= computed {
+                untouched = pending.first_row + (pending.buffer.len() + self.rows.len()).saturating_sub(pending.total_rows);
+            }
+        }
+        let previous = self.highlight_state.split_off(from);
+        let shift = self.rows.len() as isize - computed as isize;
+        let mut state = match from.checked_sub(1) {
+            Some(above) => self.end_state(&self.rows[above], self.highlight_state[above]),
+            None => LineState::Code,
+        };
+        for row in from..self.rows.len() {
+            if row >= untouched
+                && let Some(before) = row.checked_add_signed(-shift).and_then(|at| at.checked_sub(from))
+                && previous.get(before) == Some(&state)
+            {
+                self.highlight_state.extend_from_slice(&previous[before..]); // the rest follows as it did
+                break;
+            }
+            self.highlight_state.push(state);
+            state = self.end_state(&self.rows[row], state);
+        }
+        self.highlight_valid = self.rows.len();
+    }
+    //rows from `row` down may have changed, their highlight state is recomputed before the next frame
+    fn invalidate_highlight(&mut self, row: usize) {
+        self.highlight_valid = self.highlight_valid.min(row);
+    }
+    //what the row after `line` starts inside of, when `line` starts inside of `start`
+    fn end_state(&self, line: &str, start: LineState) -> LineState {
+        let syntax = self.language.syntax();
+        let comment: Vec<char> = syntax.line_comment.chars().collect();
+        let chars: Vec<char> = line.chars().collect();
+        let mut state = start;
+        let mut i = 0;
+        while i < chars.len() {
+            if state != LineState::Code {
+                match state.closing(&chars, i) {
+                    Some(end) => (state, i) = (LineState::Code, end),
+                    None => break,
+                }
+            } else if !comment.is_empty() && chars[i..].starts_with(&comment) {
+                break; // the rest of the line is a line comment
+            } else if syntax.block_comments && chars[i..].starts_with(&['/', '*']) {
+                (state, i) = (LineState::BlockComment, i + 2);
+            } else if let Some((len, hashes)) = raw_string_start(&chars, i).filter(|_| syntax.raw_strings) {
+                (state, i) = (LineState::RawString(hashes), i + len);
+            } else if let Some(end) = char_literal_end(&chars, i).filter(|_| syntax.char_literals) {
+                i = end;
+            } else if syntax.quotes.contains(&chars[i]) {
+                (state, i) = (LineState::String(chars[i]), i + 1);
+            } else {
+                i += 1;
+            }
+        }
+        match state {
+            LineState::String(quote) if !syntax.multiline_quotes.contains(&quote) => LineState::Code, // ends with the line
+            state => state,
+        }
+    }
+
+    fn highlight_line(&self, line: &str, start: LineState)-> Vec<(String, Token)>  {
+        if self.language == Language::GitCommit && line.starts_with('#') {
+            return vec![(line.to_string(), Token::Comment)];
+        }
+        if matches!(self.language, Language::PlainText | Language::Markdown | Language::GitCommit) {
+            return vec![(line.to_string(), Token::Normal)]; // prose, keyword coloring would only be noise
+        }
+        let syntax = self.language.syntax();
+        let (keywords, types) = (syntax.keywords, syntax.types);
+        let comment: Vec<char> = self.language.line_comment().chars().collect();
+        let quotes = self.language.quotes();
+        let mut state = start;
+
         let mut result = Vec::new();
         let mut i = 0;
         let chars: Vec<char> = line.chars().collect();
         while i< chars.len() {
             let c = chars[i];
+            //Comment or string still open, possibly carried over from a line above
+            if state != LineState::Code {
+                let end = state.closing(&chars, i);
+                let stop = end.unwrap_or(chars.len()).min(chars.len());
+                if state == LineState::BlockComment {
+                    result.push((chars[i..stop].iter().collect(), Token::Comment));
+                } else {
+                    push_string(&mut result, &chars[i..stop], !matches!(state, LineState::RawString(_)));
+                }
+                if end.is_some() {
+                    state = LineState::Code;
+                }
+                i = stop;
+                continue;
+            }
+            //Block comment opener, the comment itself is colored above
+            if syntax.block_comments && chars[i..].starts_with(&['/', '*']) {
+                result.push(("/*".to_string(), Token::Comment));
+                (state, i) = (LineState::BlockComment, i + 2);
+                continue;
+            }
             //Single line comment
-            if c == '/' && i+1 < chars.len() && chars[i+1] == '/' {
-                let comment: String = line[i..].to_string();
-                result.push((comment, Color::DarkGrey));
+            if !comment.is_empty() && chars[i..].starts_with(&comment) {
+                let comment: String = chars[i..].iter().collect();
+                result.push((comment, Token::Comment));
                 break;
             }
-            //String literal
-            if c == '"' {
-                let start = i;
-                i+=1;
-                while i< chars.len() && chars[i] != '"' {
-                    i+=1;
+            //Raw string opener, r"..." or r#"..."#
+            if let Some((len, hashes)) = raw_string_start(&chars, i).filter(|_| syntax.raw_strings) {
+                result.push((chars[i..i + len].iter().collect(), Token::String));
+                (state, i) = (LineState::RawString(hashes), i + len);
+                continue;
+            }
+            //Character literal
+            if let Some(end) = char_literal_end(&chars, i).filter(|_| syntax.char_literals) {
+                push_string(&mut result, &chars[i..end], true);
+                i = end;
+                continue;
+            }
+            //String literal, closed by the same quote it opened with
+            if quotes.contains(&c) {
+                result.push((c.to_string(), Token::String));
+                (state, i) = (LineState::String(c), i + 1);
+                continue;
+            }
+            //Number
+            if c.is_ascii_digit() {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                result.push((number, Token::Number));
+                continue;
+            }
+
+            // Word (identifier/keyword/type)
+            if c.is_alphanumeric() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let kind = if keywords.contains(&word.as_str()) {
+                    Token::Keyword
+                } else if types.contains(&word.as_str()) {
+                    Token::TypeName
+                } else {
+                    Token::Normal
+                };
+                result.push((word, kind));
+                continue;
+            }
+
+            // Any other single char
+            result.push((c.to_string(), Token::Normal));
+            i += 1;
+        }
+
+        result
+    }
+    //put the cursor back where an undo step recorded it
+    fn restore_cursor(&mut self, (cursor_x, cursor_y): (usize, usize)) {
+        self.selection_anchor = None;
+        self.last_edit = None;
+        self.cursor_x = cursor_x;
+        self.cursor_y = cursor_y;
+    }
+    //start an undo step for an edit confined to count rows from first, widened to the selected rows if any
+    fn push_undo_rows(&mut self, first: usize, count: usize) {
+        self.finish_undo_step();
+        let (mut first, mut last) = (first, first + count);
+        if let Some(((start_y, _), (end_y, _))) = self.selection_range() {
+            (first, last) = (first.min(start_y), last.max(end_y + 1)); // the selection may be replaced too
+        }
+        let first = first.min(self.rows.len());
+        let count = last.min(self.rows.len()) - first;
+        self.pending_undo = Some(EditorState {
+            first_row: first,
+            buffer: self.rows[first..first + count].to_vec(),
+            total_rows: self.rows.len(),
+            cursor_x: self.cursor_x,
+            cursor_y: self.cursor_y,
+        });
+        self.redo_stack.clear(); // Clear redo history on new edit
+        self.last_edit = None; // the next edit starts a fresh step
+    }
+    //turn the pending edit into an undo step holding only the rows that really changed
+    fn finish_undo_step(&mut self) {
+        self.last_edit = None; // whatever comes next cannot grow a closed step
+        let Some(state) = self.pending_undo.take() else { return };
+        let old = state.buffer;
+        let new_len = (old.len() + self.rows.len()).saturating_sub(state.total_rows);
+        let end = (state.first_row + new_len).min(self.rows.len());
+        let new = &self.rows[state.first_row.min(end)..end];
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+        if prefix + suffix == old.len() && old.len() == new.len() {
+            return; // nothing changed, e.g. Backspace at the very start of the buffer
+        }
+        let row = state.first_row + prefix;
+        let step = UndoStep {
+            ops: edit_ops(row, &new[prefix..new.len() - suffix], &old[prefix..old.len() - suffix]),
+            row,
+            before: (state.cursor_x, state.cursor_y),
+            after: (self.cursor_x, self.cursor_y),
+        };
+        self.invalidate_highlight(row);
+        push_capped(&mut self.undo_stack, step);
+    }
+    //start an undo step unless this edit just continues the previous one of the same kind,
+    //the pending step then simply grows to include it
+    fn push_undo_coalesced(&mut self, kind: EditKind, boundary: bool) {
+        let continues = self.last_edit.is_some_and(|(last, y, x, time)| {
+            last == kind && (y, x) == (self.cursor_y, self.cursor_x) && time.elapsed() < UNDO_COALESCE_WINDOW
+        });
+        if boundary || !continues {
+            match kind {
+                EditKind::Insert => self.push_undo_rows(self.cursor_y, 1),
+                EditKind::Delete => self.push_undo_rows(self.cursor_y.saturating_sub(1), 2), // may join the line above
+            }
+        }
+    }
+    //remember a coalescable edit that just left the cursor where it is
+    fn note_edit(&mut self, kind: EditKind) {
+        self.last_edit = Some((kind, self.cursor_y, self.cursor_x, Instant::now()));
+    }
+    //start search prompt
+    fn start_search(&mut self) {
+        self.search_origin = (self.cursor_x, self.cursor_y, self.col_offset, self.row_offset);
+        self.selection_anchor = None;
+        self.search_mode = true;
+        self.search_query.clear();
+        self.search_results.clear();
+        self.current_match = 0;
+    }
+    //search rows for query and keep it in search_results
+    fn perform_search(&mut self){
+        self.collect_matches();
+        self.current_match = 0;
+        if let Some(&(row,col,_)) = self.search_results.first(){
+            self.cursor_y = row;
+            self.cursor_x = col;
+            self.remember_column();
+            self.scroll_to_cursor();
+        } else {
+            // nothing matches any more, don't leave the cursor on what a shorter query found
+            (self.cursor_x, self.cursor_y, self.col_offset, self.row_offset) = self.search_origin;
+        }
+    }
+    //fill search_results with every span where the query occurs, without moving the cursor
+    fn collect_matches(&mut self) {
+        self.search_error = None;
+        if self.search_regex {
+            self.collect_regex_matches();
+            return;
+        }
+        self.search_results.clear();
+        if self.search_query.is_empty() {
+            return;
+        }
+        let q = self.search_query.to_lowercase();
+        let len = grapheme_len(&q);
+        let mut results = Vec::new();
+        for (i, line) in self.rows.iter().enumerate(){
+            let line_lower = line.to_lowercase();
+            let mut start = 0;
+            while let Some(pos) = line_lower[start..].find(&q){
+                let byte_pos = start + pos;
+                let col = grapheme_len(&line_lower[..byte_pos]); //store the match columns as grapheme indices
+                results.push((i, col, col + len));
+                start = byte_pos + q.chars().next().map_or(1, char::len_utf8); // continue searching after the first char of the match
+            }
+        }
+        self.search_results = results;
+    }
+
+    //regex flavour of collect_matches; a pattern that does not compile keeps the previous results
+    fn collect_regex_matches(&mut self) {
+        if self.search_query.is_empty() {
+            self.search_results.clear();
+            return;
+        }
+        let re = match regex::RegexBuilder::new(&self.search_query).case_insensitive(true).build() {
+            Ok(re) => re,
+            Err(e) => {
+                // keep only the last line of the error, that is the one saying what is wrong
+                self.search_error = Some(e.to_string().lines().last().unwrap_or("invalid pattern").trim().to_string());
+                return;
+            }
+        };
+        let mut results = Vec::new();
+        for (i, line) in self.rows.iter().enumerate() {
+            for m in re.find_iter(line).filter(|m| !m.is_empty()) {
+                results.push((i, grapheme_len(&line[..m.start()]), grapheme_len(&line[..m.end()])));
+            }
+        }
+        self.search_results = results;
+    }
+    //ordered (start, end) of the selection as (row, col) pairs, None when nothing is selected
+    fn selection_range(&self) -> Option<((usize, usize), (usize, usize))> {
+        let anchor = self.selection_anchor?;
+        let cursor = (self.cursor_y, self.cursor_x);
+        match anchor.cmp(&cursor) {
+            std::cmp::Ordering::Less => Some((anchor, cursor)),
+            std::cmp::Ordering::Greater => Some((cursor, anchor)),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+    //remove the selected text and leave the cursor where it started, returns false if there was no selection
+    fn delete_selection(&mut self) -> bool {
+        let Some(((start_y, start_x), (end_y, end_x))) = self.selection_range() else {
+            self.selection_anchor = None;
+            return false;
+        };
+        self.selection_anchor = None;
+        let tail = self.rows[end_y][byte_index_of(&self.rows[end_y], end_x)..].to_string();
+        let line = &mut self.rows[start_y];
+        line.truncate(byte_index_of(line, start_x));
+        line.push_str(&tail);
+        self.rows.drain(start_y + 1..=end_y);
+        self.cursor_y = start_y;
+        self.cursor_x = start_x;
+        self.dirty = true;
+        true
+    }
+    //evaluate the selected arithmetic (or the whitespace-delimited word under the cursor) and
+    //either replace it with the result or append " = result" after it
+    fn evaluate_expression(&mut self, append: bool) {
+        let (start, end) = match self.selection_range() {
+            Some(range) => range,
+            None => {
+                let Some(line) = self.rows.get(self.cursor_y) else { return };
+                let graphemes: Vec<&str> = line.graphemes(true).collect();
+                let x = self.cursor_x.min(graphemes.len());
+                let blank = |g: &&str| g.trim().is_empty();
+                let from = x - graphemes[..x].iter().rev().take_while(|g| !blank(g)).count();
+                let to = x + graphemes[x..].iter().take_while(|g| !blank(g)).count();
+                ((self.cursor_y, from), (self.cursor_y, to))
+            }
+        };
+        // select the expression to read it, and put everything back if there is nothing to replace it with
+        let before = (self.selection_anchor, self.cursor_x, self.cursor_y);
+        self.selection_anchor = Some(start);
+        self.cursor_y = end.0;
+        self.cursor_x = end.1;
+        let text = self.selected_text().join(" ");
+        let value = match evaluate(&text) {
+            Ok(value) => value,
+            Err(e) => {
+                (self.selection_anchor, self.cursor_x, self.cursor_y) = before;
+                if text.trim().is_empty() {
+                    self.set_status_message("No expression to evaluate");
+                } else {
+                    self.set_status_message(format!("Cannot evaluate: {}", e));
+                }
+                return;
+            }
+        };
+        self.push_undo_rows(self.cursor_y, 1); // widened to the expression's rows
+        if append {
+            self.selection_anchor = None; // keep the expression, the result goes after it
+            self.insert_text(&[format!(" = {}", value)]);
+        } else {
+            self.delete_selection();
+            self.insert_text(&[value.to_string()]);
+        }
+        self.set_status_message(format!("= {}", value));
+    }
+    //remove one indentation level (one tab or up to tab_width spaces) from the start of a row, returns how many columns went
+    fn unindent_line(&mut self, row: usize) -> usize {
+        let tab_width = self.tab_width;
+        let line = &mut self.rows[row];
+        let removed = if line.starts_with('\t') {
+            1
+        } else {
+            line.chars().take(tab_width).take_while(|&c| c == ' ').count()
+        };
+        if removed > 0 {
+            line.drain(..removed); // tabs and spaces are one byte each
+            self.dirty = true;
+        }
+        removed
+    }
+    //copy of the selected text, one entry per line
+    fn selected_text(&self) -> Vec<String> {
+        let Some(((start_y, start_x), (end_y, end_x))) = self.selection_range() else {
+            return Vec::new();
+        };
+        (start_y..=end_y)
+            .map(|y| {
+                let line = &self.rows[y];
+                let from = if y == start_y { byte_index_of(line, start_x) } else { 0 };
+                let to = if y == end_y { byte_index_of(line, end_x) } else { line.len() };
+                line[from..to].to_string()
+            })
+            .collect()
+    }
+    //store yanked lines, mirroring them to the system clipboard when that feature is built in
+    fn copy_to_clipboard(&mut self, lines: Vec<String>) {
+        #[cfg(feature = "system-clipboard")]
+        if arboard::Clipboard::new().and_then(|mut cb| cb.set_text(lines.join("\n"))).is_err() {
+            self.set_status_message("System clipboard unavailable");
+        }
+        #[cfg(not(any(feature = "system-clipboard", test)))] // tests have no terminal to ask
+        {
+            // without the clipboard crate ask the terminal to set it (OSC 52), ignored where unsupported
+            let mut stdout = io::stdout();
+            let _ = write!(stdout, "\x1b]52;c;{}\x07", base64_encode(lines.join("\n").as_bytes()));
+            let _ = stdout.flush();
+        }
+        self.clipboard = lines;
+    }
+    //the selected text, or the current line (with its line break, so it pastes as a whole line)
+    fn selection_or_line(&self) -> Vec<String> {
+        if self.selection_range().is_some() {
+            return self.selected_text();
+        }
+        let line = self.rows.get(self.cursor_y).cloned().unwrap_or_default();
+        vec![line, String::new()]
+    }
+    //lines to paste: the internal clipboard, or the system one when asked for or when ours is empty
+    #[cfg_attr(not(feature = "system-clipboard"), allow(unused_variables))]
+    fn clipboard_for_paste(&mut self, from_system: bool) -> Vec<String> {
+        #[cfg(feature = "system-clipboard")]
+        if from_system || self.clipboard.is_empty() {
+            match arboard::Clipboard::new().and_then(|mut cb| cb.get_text()) {
+                Ok(text) => return text.lines().map(|l| l.to_string()).collect(),
+                Err(_) => self.set_status_message("System clipboard unavailable"),
+            }
+        }
+        self.clipboard.clone()
+    }
+    //insert lines of text at the cursor: one entry goes inline, more split the current line around them
+    fn insert_text(&mut self, lines: &[String]) {
+        let (Some(first), Some(last)) = (lines.first(), lines.last()) else { return };
+        let (cursor_x, cursor_y) = (self.cursor_x, self.cursor_y);
+        let line = &mut self.rows[cursor_y];
+        let tail = line.split_off(byte_index_of(line, cursor_x));
+        line.push_str(first);
+        if lines.len() == 1 {
+            self.cursor_x += grapheme_len(first);
+        } else {
+            let middle = lines[1..lines.len() - 1].iter().cloned();
+            let at = cursor_y + 1;
+            self.rows.splice(at..at, middle.chain(std::iter::once(last.clone())));
+            self.cursor_y += lines.len() - 1;
+            self.cursor_x = grapheme_len(last);
+        }
+        let row = self.cursor_y;
+        self.rows[row].push_str(&tail);
+        self.dirty = true;
+    }
+    //search matches on a buffer row as (start_col, end_col, is_current_match)
+    fn matches_on_row(&self, row: usize) -> Vec<(usize, usize, bool)> {
+        self.search_results
+            .iter()
+            .enumerate()
+            .filter(|(_, (r, _, _))| *r == row)
+            .map(|(k, &(_, start, end))| (start, end, k == self.current_match))
+            .collect()
+    }
+    // keep the cursor inside the visible window by adjusting col_offset and row_offset
+    fn scroll_to_cursor(&mut self) {
+        if self.word_wrap {
+            self.scroll_to_wrapped_cursor();
+            return;
+        }
+        let screen_cols = self.text_cols();
+        if self.cursor_x < self.col_offset {
+            self.col_offset = self.cursor_x;
+        } else if let Some(line) = self.rows.get(self.cursor_y) {
+            // scroll right until the cursor's rendered column fits next to the » marker
+            let cursor_render = render_col(line, self.cursor_x, self.tab_width);
+            let mut col_offset = self.col_offset;
+            while col_offset < self.cursor_x {
+                let marker = usize::from(col_offset > 0);
+                if cursor_render - render_col(line, col_offset, self.tab_width) + marker < screen_cols {
+                    break;
+                }
+                col_offset += 1;
+            }
+            self.col_offset = col_offset;
+        }
+        let text_rows = self.text_rows();
+        if self.cursor_y < self.row_offset {
+            self.row_offset = self.cursor_y;
+        } else if self.cursor_y >= self.row_offset + text_rows {
+            self.row_offset = self.cursor_y + 1 - text_rows;
+        }
+    }
+    // with word wrap only row_offset moves, far enough that every screen line down to the cursor's fits
+    fn scroll_to_wrapped_cursor(&mut self) {
+        self.col_offset = 0;
+        let text_rows = self.text_rows();
+        if self.cursor_y < self.row_offset {
+            self.row_offset = self.cursor_y;
+            return;
+        }
+        if self.cursor_y >= self.row_offset + text_rows {
+            self.row_offset = self.cursor_y + 1 - text_rows; // every row takes at least one screen line
+        }
+        let cursor_lines = self.wrap_starts(self.cursor_y).partition_point(|&start| start <= self.cursor_x);
+        let mut needed = cursor_lines + (self.row_offset..self.cursor_y).map(|row| self.wrap_starts(row).len()).sum::<usize>();
+        while needed > text_rows && self.row_offset < self.cursor_y {
+            needed -= self.wrap_starts(self.row_offset).len();
+            self.row_offset += 1;
+        }
+    }
+    // first grapheme of each screen line a row is wrapped onto, just [0] when it fits or wrap is off
+    fn wrap_starts(&self, row: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        let Some(line) = self.rows.get(row).filter(|_| self.word_wrap) else {
+            return starts;
+        };
+        let width = self.text_cols();
+        let (mut render_at, mut line_start) = (0, 0);
+        for (col, g) in line.graphemes(true).enumerate() {
+            let g_width = grapheme_width(g, render_at, self.tab_width);
+            // the same test draw_rows uses to stop a screen line, a grapheme wider than the window gets one to itself
+            if render_at + g_width - line_start > width && starts.last() != Some(&col) {
+                starts.push(col);
+                line_start = render_at;
+            }
+            render_at += g_width;
+        }
+        starts
+    }
+    // last column the cursor can take on the screen line starting at first_col, the next one begins after it
+    fn screen_line_end(&self, row: usize, first_col: usize) -> usize {
+        match self.wrap_starts(row).into_iter().find(|&start| start > first_col) {
+            Some(next) => next - 1,
+            None => self.rows.get(row).map_or(0, |line| grapheme_len(line)),
+        }
+    }
+    // (row, first grapheme) shown on each text line of the pane, top to bottom, ending with the buffer
+    fn screen_lines(&self) -> Vec<(usize, usize)> {
+        let text_rows = self.text_rows();
+        let last = self.rows.len().min(self.row_offset + text_rows);
+        if !self.word_wrap {
+            return (self.row_offset..last).map(|row| (row, self.col_offset)).collect();
+        }
+        (self.row_offset..last)
+            .flat_map(|row| self.wrap_starts(row).into_iter().map(move |start| (row, start)))
+            .take(text_rows)
+            .collect()
+    }
+    // screen line of the pane the cursor is on and the first grapheme drawn there
+    fn cursor_screen_line(&self) -> (usize, usize) {
+        if !self.word_wrap {
+            return (self.cursor_y.saturating_sub(self.row_offset), self.col_offset);
+        }
+        let screen_lines = self.screen_lines();
+        match screen_lines.iter().rposition(|&(row, start)| row == self.cursor_y && start <= self.cursor_x) {
+            Some(i) => (i, screen_lines[i].1),
+            None => (screen_lines.len().saturating_sub(1), 0), // below a line taller than the pane
+        }
+    }
+    // adopt a new terminal size, keeping the cursor in view
+    fn resize(&mut self, cols: u16, rows: u16) {
+        self.screen_cols = cols;
+        self.screen_rows = rows;
+        self.last_frame.clear(); // every line moved or changed width, repaint from scratch
+        self.scroll_to_cursor();
+    }
+    // width of the line number gutter: the digits of the last line number plus padding, 0 when hidden
+    fn gutter_width(&self) -> usize {
+        if !self.show_line_numbers {
+            return 0;
+        }
+        self.rows.len().max(1).to_string().len() + 2
+    }
+    // number of screen columns left for text after the gutter
+    fn text_cols(&self) -> usize {
+        (self.screen_cols as usize).saturating_sub(self.gutter_width()).max(1)
+    }
+    // number of screen lines available for text in the focused pane (its last one is the status bar)
+    fn text_rows(&self) -> usize {
+        self.pane_extent(self.focus_top).1.saturating_sub(1).max(1)
+    }
+    // first screen line and height, status bar included, of the top or bottom pane; unsplit there is one full-screen pane
+    fn pane_extent(&self, top: bool) -> (usize, usize) {
+        let rows = self.screen_rows as usize;
+        if self.split.is_none() {
+            return (0, rows);
+        }
+        let top_height = rows / 2;
+        if top { (0, top_height) } else { (top_height, rows - top_height) }
+    }
+    fn draw_search_prompt(&self, out: &mut Vec<u8>) -> std::io::Result<()> {
+        let prompt = if !self.replace_mode {
+            let counter = match self.search_results.len() {
+                _ if self.search_query.is_empty() => String::new(),
+                0 => " (no matches)".to_string(),
+                count => format!(" (match {}/{})", self.current_match + 1, count),
+            };
+            let label = if self.search_regex { "Search (regex)" } else { "Search" };
+            match &self.search_error {
+                Some(err) => format!("{}: {} [{}]", label, self.search_query, err),
+                None => format!("{}: {}{}", label, self.search_query, counter),
+            }
+        } else {
+            match self.replace_stage {
+                ReplaceStage::Search => format!("Replace: {}", self.search_query),
+                ReplaceStage::Replacement => format!("Replace '{}' with: {}", self.search_query, self.replace_query),
+                ReplaceStage::Confirm => format!(
+                    "Replace '{}' with '{}'? y/Enter: yes, n: skip, a: rest, Alt+A: all, Esc: stop",
+                    self.search_query, self.replace_query
+                ),
+            }
+        };
+        self.draw_prompt(out, &prompt)
+    }
+    //draw a minibuffer prompt over the status bar
+    fn draw_prompt(&self, out: &mut Vec<u8>, prompt: &str) -> std::io::Result<()> {
+        use crossterm::style::{SetAttribute, Attribute, SetBackgroundColor, SetForegroundColor, Color};
+        execute!(
+            out,
+            SetBackgroundColor(self.theme.prompt_bg),
+            SetForegroundColor(self.theme.prompt_fg),
+            SetAttribute(Attribute::Bold),
+            Print(prompt.chars().take(self.screen_cols as usize).collect::<String>()), // never wrap onto a new line
+            SetAttribute(Attribute::Reset),
+            SetForegroundColor(Color::Reset),
+            SetBackgroundColor(Color::Reset),
+        )
+    }
+    fn process_search_keypress(&mut self, event: KeyEvent) -> bool {
+        if event.kind != KeyEventKind::Press {
+            return false;
+        }
+        if self.replace_mode && self.replace_stage != ReplaceStage::Search {
+            self.process_replace_keypress(event);
+            return false;
+        }
+        match event.code {
+            KeyCode::Enter if self.replace_mode => {
+                // the text to find is done, ask for what to put in its place
+                if self.search_results.is_empty() {
+                    self.set_status_message("No matches to replace");
+                } else {
+                    self.replace_stage = ReplaceStage::Replacement;
+                }
+            }
+            KeyCode::Up => self.step_match(false),
+            KeyCode::Enter if event.modifiers.contains(KeyModifiers::SHIFT) => self.step_match(false),
+            KeyCode::Down | KeyCode::Enter => self.step_match(true),
+            KeyCode::Left | KeyCode::Right | KeyCode::Home | KeyCode::End => {
+                // accept: leave search with the cursor on the current match
+                self.search_mode = false;
+                self.search_query.clear();
+                self.search_results.clear();
+            }
+            KeyCode::Char('r') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.search_regex = !self.search_regex;
+                self.perform_search();
+            }
+            KeyCode::Backspace => {
+                self.search_query.pop();
+                self.perform_search();
+            }
+            KeyCode::Char(c)
+                if !event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+                    || event.modifiers.contains(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                // a Ctrl/Alt chord with no binding here is not text, AltGr arrives as Ctrl+Alt and is
+                self.search_query.push(c);
+                self.perform_search();
+            }
+            _ => {}
+        }
+        false
+    }
+    //move to the next (or previous) match, wrapping around at either end
+    fn step_match(&mut self, forward: bool) {
+        let count = self.search_results.len();
+        if count == 0 {
+            return;
+        }
+        self.current_match = if forward {
+            (self.current_match + 1) % count
+        } else {
+            (self.current_match + count - 1) % count
+        };
+        if forward && self.current_match == 0 {
+            self.set_status_message("Search wrapped to top");
+        } else if !forward && self.current_match == count - 1 {
+            self.set_status_message("Search wrapped to bottom");
+        }
+        let (row, col, _) = self.search_results[self.current_match];
+        self.cursor_y = row;
+        self.cursor_x = col;
+        self.remember_column();
+        self.scroll_to_cursor();
+    }
+    //leave search (and replace) mode and drop the match highlights
+    fn end_search(&mut self) {
+        self.search_mode = false;
+        self.replace_mode = false;
+        self.search_query.clear();
+        self.search_results.clear();
+    }
+    //reduced keymap of the read-only pager, returns true when it should quit
+    fn process_view_keypress(&mut self, event: KeyEvent) -> bool {
+        if event.kind != KeyEventKind::Press {
+            return false;
+        }
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        match event.code {
+            KeyCode::Char('q') => return true,
+            KeyCode::Char(' ') => return self.process_keypress(key(KeyCode::PageDown)),
+            KeyCode::Char('b') => return self.process_keypress(key(KeyCode::PageUp)),
+            KeyCode::Char('g') => return self.process_keypress(KeyEvent::new(KeyCode::Home, KeyModifiers::CONTROL)),
+            KeyCode::Char('G') => return self.process_keypress(KeyEvent::new(KeyCode::End, KeyModifiers::CONTROL)),
+            KeyCode::Char('/') => self.start_search(),
+            KeyCode::Char('F') => self.toggle_follow(),
+            // only movement reaches the editor, so the buffer and undo history are never touched
+            KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down
+                | KeyCode::Home | KeyCode::End | KeyCode::PageUp | KeyCode::PageDown => {
+                return self.process_keypress(event);
+            }
+            _ => {}
+        }
+        false
+    }
+    //start or stop following the file, starting reads it again from the top and goes to its end
+    fn toggle_follow(&mut self) {
+        self.follow = !self.follow;
+        if !self.follow {
+            self.set_status_message("Stopped following");
+            return;
+        }
+        (self.follow_offset, self.follow_partial) = (0, false);
+        self.rows.clear();
+        self.follow_file();
+        if self.follow {
+            self.set_status_message("Following, F to stop");
+        }
+    }
+    //while following, add what was appended to the file since the last look, staying at the end when already there
+    fn follow_file(&mut self) {
+        if !self.follow {
+            return;
+        }
+        let at_end = self.cursor_y + 1 >= self.rows.len();
+        let first = match self.read_appended() {
+            Ok(Some(first)) => first,
+            Ok(None) => return,
+            Err(err) => {
+                self.follow = false;
+                if self.rows.is_empty() {
+                    self.rows.push(String::new());
+                }
+                self.invalidate_highlight(0);
+                self.cursor_y = self.cursor_y.min(self.rows.len() - 1);
+                self.cursor_x = self.cursor_x.min(grapheme_len(&self.rows[self.cursor_y]));
+                self.set_status_message(format!("Stopped following: {}", err));
+                return;
+            }
+        };
+        self.invalidate_highlight(first);
+        if at_end {
+            self.cursor_y = self.rows.len() - 1;
+            self.cursor_x = 0;
+        }
+        self.cursor_y = self.cursor_y.min(self.rows.len() - 1);
+        self.cursor_x = self.cursor_x.min(grapheme_len(&self.rows[self.cursor_y]));
+        self.scroll_to_cursor();
+    }
+    //read the file past follow_offset into rows, returns the first row that changed, None when nothing did
+    fn read_appended(&mut self) -> io::Result<Option<usize>> {
+        let Some(name) = self.filename.clone() else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no file"));
+        };
+        let mut file = fs::File::open(name)?;
+        let len = file.metadata()?.len();
+        if len < self.follow_offset {
+            // truncated or replaced, e.g. a rotated log, start over
+            (self.follow_offset, self.follow_partial) = (0, false);
+            self.rows.clear();
+        } else if len == self.follow_offset && !self.rows.is_empty() {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start(self.follow_offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let text = String::from_utf8_lossy(&bytes);
+        if self.follow_partial {
+            self.rows.pop(); // read again together with the rest of its line
+        }
+        let first = self.rows.len();
+        let complete = text.rfind('\n').map_or(0, |end| end + 1);
+        self.rows.extend(text[..complete].lines().map(str::to_string));
+        self.follow_partial = complete < text.len() || self.rows.is_empty();
+        if self.follow_partial {
+            self.rows.push(text[complete..].to_string()); // also the one empty row of an empty file
+        }
+        self.follow_offset += complete as u64;
+        Ok(Some(first.min(self.rows.len() - 1)))
+    }
+    //start find-and-replace: the search prompt first collects the text to find
+    fn start_replace(&mut self) {
+        if self.readonly {
+            self.set_status_message("Read-only file, nothing can be replaced");
+            return;
+        }
+        self.start_search();
+        self.replace_mode = true;
+        self.replace_query.clear();
+        self.replace_stage = ReplaceStage::Search;
+    }
+    //keys for the replacement and confirm steps of find-and-replace
+    fn process_replace_keypress(&mut self, event: KeyEvent) {
+        match (self.replace_stage, event.code) {
+            (ReplaceStage::Replacement, KeyCode::Enter) => self.replace_stage = ReplaceStage::Confirm,
+            (ReplaceStage::Replacement, KeyCode::Backspace) => {
+                self.replace_query.pop();
+            }
+            (ReplaceStage::Replacement, KeyCode::Char(c)) => self.replace_query.push(c),
+            (ReplaceStage::Confirm, KeyCode::Char('a') | KeyCode::Char('A')) if event.modifiers.contains(KeyModifiers::ALT) => {
+                self.perform_replace_all();
+            }
+            (ReplaceStage::Confirm, KeyCode::Enter | KeyCode::Char('y')) => self.replace_current(),
+            (ReplaceStage::Confirm, KeyCode::Char('n')) => self.skip_match(),
+            (ReplaceStage::Confirm, KeyCode::Char('a')) => self.replace_from(self.current_match),
+            _ => {}
+        }
+    }
+    //replace the text of one match, returns how many graphemes were inserted
+    fn replace_match(&mut self, row: usize, col: usize, end_col: usize) -> usize {
+        let replacement = self.replace_query.clone();
+        let line = &mut self.rows[row];
+        let start = byte_index_of(line, col);
+        let end = byte_index_of(line, end_col);
+        line.replace_range(start..end, &replacement);
+        self.dirty = true;
+        grapheme_len(&replacement)
+    }
+    //replace the current match and move on to the next one after it
+    fn replace_current(&mut self) {
+        let Some(&(row, col, end)) = self.search_results.get(self.current_match) else {
+            return;
+        };
+        self.push_undo_rows(row, 1);
+        let inserted = self.replace_match(row, col, end);
+        self.collect_matches();
+        // continue after the inserted text so a replacement containing the query is not matched again
+        let next = self.search_results.iter().position(|&(r, c, _)| (r, c) >= (row, col + inserted));
+        self.cursor_y = row;
+        self.cursor_x = col + inserted;
+        self.goto_replace_match(next);
+    }
+    //leave the current match as it is and move to the next one
+    fn skip_match(&mut self) {
+        let next = self.current_match + 1;
+        self.goto_replace_match((next < self.search_results.len()).then_some(next));
+    }
+    //put the cursor on the given match, or finish replacing when there is none left
+    fn goto_replace_match(&mut self, next: Option<usize>) {
+        match next.and_then(|k| self.search_results.get(k).map(|&m| (k, m))) {
+            Some((k, (row, col, _))) => {
+                self.current_match = k;
+                self.cursor_y = row;
+                self.cursor_x = col;
+            }
+            None => {
+                self.end_search(); // nothing left, close the prompt so the message shows
+                self.set_status_message("No more matches");
+            }
+        }
+        self.remember_column();
+        self.scroll_to_cursor();
+    }
+    //replace every match as a single undo step
+    fn perform_replace_all(&mut self) {
+        self.replace_from(0);
+    }
+    //replace the matches from index `first` to the end as a single undo step
+    fn replace_from(&mut self, first: usize) {
+        if first >= self.search_results.len() {
+            self.set_status_message("No matches to replace");
+            return;
+        }
+        // only the rows with matches change
+        let rows = self.search_results[first..].iter().map(|&(row, _, _)| row);
+        let (top, bottom) = (rows.clone().min().unwrap_or(0), rows.max().unwrap_or(0));
+        self.push_undo_rows(top, bottom + 1 - top);
+        let mut count = 0;
+        let mut last: Option<(usize, usize)> = None;
+        // go backwards so earlier columns on a row stay valid after each replacement
+        let results = std::mem::take(&mut self.search_results); // end_search below clears them anyway
+        for &(row, col, end) in results[first..].iter().rev() {
+            if let Some((last_row, last_col)) = last
+                && last_row == row
+                && end > last_col {
+                continue; // overlaps the match just replaced
+            }
+            self.replace_match(row, col, end);
+            last = Some((row, col));
+            count += 1;
+        }
+        self.end_search();
+        self.cursor_x = self.cursor_x.min(grapheme_len(&self.rows[self.cursor_y]));
+        self.remember_column();
+        self.scroll_to_cursor();
+        self.set_status_message(format!("Replaced {} occurrences", count));
+    }
+    //text of the save-as or go-to-line prompt when one of them is open
+    fn input_prompt(&self) -> Option<String> {
+        if self.save_as_confirm {
+            Some("File exists, overwrite? [y/N] ".to_string())
+        } else if self.save_as_mode {
+            Some(format!("Save as: {}", self.save_as_query))
+        } else if self.goto_mode {
+            Some(format!("Go to line[:col]: {}", self.goto_query))
+        } else if self.open_mode {
+            Some(format!("Open: {}", self.open_query))
+        } else if self.cd_mode {
+            Some(format!("Change directory: {}", self.cd_query))
+        } else {
+            None
+        }
+    }
+    //open the go-to-line prompt
+    fn start_goto(&mut self) {
+        self.goto_mode = true;
+        self.goto_query.clear();
+    }
+    fn process_goto_keypress(&mut self, event: KeyEvent) {
+        if event.kind != KeyEventKind::Press {
+            return;
+        }
+        match event.code {
+            KeyCode::Enter => {
+                self.goto_mode = false;
+                match parse_goto(&self.goto_query) {
+                    Some((line, col)) => self.goto_line(line, col),
+                    None => self.set_status_message("Invalid line number"),
+                }
+            }
+            KeyCode::Backspace => {
+                self.goto_query.pop();
+            }
+            KeyCode::Char(c) => {
+                self.goto_query.push(c);
+            }
+            _ => {}
+        }
+    }
+    fn process_open_keypress(&mut self, event: KeyEvent) {
+        if event.kind != KeyEventKind::Press {
+            return;
+        }
+        match event.code {
+            KeyCode::Enter if !self.open_query.is_empty() => {
+                self.open_mode = false;
+                let name = std::mem::take(&mut self.open_query);
+                if let Err(e) = self.open_buffer(&self.resolve(&name)) {
+                    self.set_status_message(format!("Failed to open {}: {}", name, e));
+                }
+            }
+            KeyCode::Backspace => {
+                self.open_query.pop();
+            }
+            KeyCode::Char(c) => {
+                self.open_query.push(c);
+            }
+            _ => {}
+        }
+    }
+    fn process_cd_keypress(&mut self, event: KeyEvent) {
+        if event.kind != KeyEventKind::Press {
+            return;
+        }
+        match event.code {
+            KeyCode::Enter if !self.cd_query.is_empty() => {
+                self.cd_mode = false;
+                let name = std::mem::take(&mut self.cd_query);
+                self.change_directory(&name);
+            }
+            KeyCode::Backspace => {
+                self.cd_query.pop();
+            }
+            KeyCode::Char(c) => {
+                self.cd_query.push(c);
+            }
+            _ => {}
+        }
+    }
+    //make `dir` the editor's cwd; the process keeps its own, so commands still run where they did
+    fn change_directory(&mut self, dir: &str) {
+        let dir = match self.home.as_ref().zip(dir.strip_prefix('~')) {
+            Some((home, rest)) => home.join(rest.trim_start_matches('/')),
+            None => self.cwd.join(dir),
+        };
+        match fs::canonicalize(&dir) {
+            Ok(dir) if dir.is_dir() => {
+                self.cwd = dir;
+                let shown = display_path(&self.cwd, &self.cwd, self.home.as_deref(), PathDisplay::Absolute);
+                self.set_status_message(format!("Working directory: {}", shown));
+            }
+            Ok(_) => self.set_status_message(format!("Not a directory: {}", dir.display())),
+            Err(err) => self.set_status_message(format!("Cannot change to {}: {}", dir.display(), err)),
+        }
+    }
+    //a path typed into a prompt, relative to the editor's cwd rather than the process's
+    fn resolve(&self, name: &str) -> String {
+        self.cwd.join(name).to_string_lossy().into_owned()
+    }
+    //show a file in a buffer of its own, switching to it when it is already open; a missing file starts empty
+    fn open_buffer(&mut self, filename: &str) -> std::io::Result<()> {
+        let absolute = fs::canonicalize(filename).ok();
+        let open_at = self.buffers.iter().position(|buffer| absolute.is_some() && buffer.absolute_path == absolute);
+        if let Some(index) = open_at {
+            self.switch_buffer(index);
+            return Ok(());
+        }
+        let previous = self.active_buffer;
+        self.new_buffer();
+        let opened = if Path::new(filename).exists() {
+            self.open(filename)
+        } else {
+            self.set_filename(filename.to_string());
+            Ok(())
+        };
+        if opened.is_err() {
+            // nothing to show, drop the buffer that was made for it
+            self.switch_buffer(previous);
+            self.buffers.pop();
+        }
+        opened
+    }
+    //edit the user config file in a buffer, making its directory so the first save of it works
+    fn open_config(&mut self) {
+        let Some(path) = Config::user_path() else {
+            self.set_status_message("No config location, neither XDG_CONFIG_HOME nor HOME is set");
+            return;
+        };
+        if let Some(dir) = path.parent()
+            && let Err(err) = fs::create_dir_all(dir) {
+            self.set_status_message(format!("Cannot create {}: {}", dir.display(), err));
+            return;
+        }
+        match self.open_buffer(&path.to_string_lossy()) {
+            Ok(()) => self.set_status_message("Config changes apply the next time the editor starts"),
+            Err(err) => self.set_status_message(format!("Cannot open {}: {}", path.display(), err)),
+        }
+    }
+    //add an empty buffer after the others and show it
+    fn new_buffer(&mut self) {
+        self.buffers.push(Buffer::empty());
+        self.switch_buffer(self.buffers.len() - 1);
+    }
+    //show the next (or previous) open buffer, wrapping around
+    fn cycle_buffer(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        if count == 1 {
+            self.set_status_message("No other buffers open, Alt+o opens one");
+            return;
+        }
+        let step = if forward { 1 } else { count - 1 };
+        self.switch_buffer((self.active_buffer + step) % count);
+    }
+    //show buffer `index`
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.active_buffer {
+            return;
+        }
+        self.finish_undo_step(); // an edit in progress belongs to the buffer it was made in
+        self.active_buffer = index;
+        self.search_results.clear(); // matches were found in the other file
+    }
+    //split the screen into two panes on the same spot, or go back to the focused pane alone
+    fn toggle_split(&mut self) {
+        if self.split.take().is_none() {
+            if self.screen_rows < 4 {
+                self.set_status_message("Terminal too small to split");
+                return;
+            }
+            self.split = Some(self.view());
+            self.focus_top = true;
+        }
+        self.last_frame.clear(); // every line moves
+        self.scroll_to_cursor();
+    }
+    //move focus to the other pane
+    fn focus_other_pane(&mut self) {
+        self.finish_undo_step();
+        self.swap_pane();
+        self.search_results.clear(); // they belong to the view that had focus
+        self.scroll_to_cursor();
+    }
+    //the focused pane's view
+    fn view(&self) -> Pane {
+        Pane {
+            buffer: self.active_buffer,
+            cursor_x: self.cursor_x,
+            cursor_y: self.cursor_y,
+            row_offset: self.row_offset,
+            col_offset: self.col_offset,
+            desired_x: self.desired_x,
+            selection_anchor: self.selection_anchor,
+        }
+    }
+    //make the other pane's view the focused one and keep the focused one in its place
+    fn swap_pane(&mut self) {
+        let Some(pane) = self.split.replace(self.view()) else { return };
+        self.active_buffer = pane.buffer;
+        self.focus_top = !self.focus_top;
+        // an edit made through the other pane can leave this view past the end of a shared buffer
+        self.cursor_y = pane.cursor_y.min(self.rows.len().saturating_sub(1));
+        self.cursor_x = pane.cursor_x.min(self.rows.get(self.cursor_y).map_or(0, |l| grapheme_len(l)));
+        self.row_offset = pane.row_offset;
+        self.col_offset = pane.col_offset;
+        self.desired_x = pane.desired_x;
+        self.selection_anchor = pane.selection_anchor.filter(|&(row, _)| row < self.rows.len());
+    }
+    //draw the pane without focus and its status line by briefly giving it the focus
+    fn draw_other_pane(&mut self) -> std::io::Result<Vec<Vec<u8>>> {
+        // matches and messages belong to the focused pane
+        let search_results = std::mem::take(&mut self.search_results);
+        let status_message = self.status_message.take();
+        let other_buffer = self.split.as_ref().is_some_and(|pane| pane.buffer != self.active_buffer);
+        self.swap_pane();
+        if other_buffer {
+            self.recompute_highlight_state();
+        }
+        self.scroll_to_cursor(); // a resize may have left its cursor outside it
+        let mut status = Vec::new();
+        let drawn = self.draw_rows().and_then(|mut lines| {
+            self.draw_status_bar(&mut status)?;
+            lines.push(status);
+            Ok(lines)
+        });
+        self.swap_pane();
+        self.search_results = search_results;
+        self.status_message = status_message;
+        drawn
+    }
+    //number of open buffers with unsaved changes
+    fn dirty_buffer_count(&self) -> usize {
+        self.buffers.iter().filter(|buffer| buffer.dirty).count()
+    }
+    //move to a 1-based line and column, clamped to the buffer, with the line centered in the view
+    fn goto_line(&mut self, line: usize, col: usize) {
+        self.selection_anchor = None;
+        self.cursor_y = line.clamp(1, self.rows.len().max(1)) - 1;
+        let len = self.rows.get(self.cursor_y).map_or(0, |l| grapheme_len(l));
+        self.cursor_x = col.saturating_sub(1).min(len);
+        self.row_offset = self.cursor_y.saturating_sub(self.text_rows() / 2);
+        self.remember_column();
+        self.scroll_to_cursor();
+    }
+    //go by indentation to the enclosing line (Alt+Up) or the next line at the same depth (Alt+Down),
+    //onto its first non-blank column
+    fn jump_in_outline(&mut self, sibling: bool) {
+        let target = if sibling {
+            next_sibling_row(&self.rows, self.cursor_y, self.tab_width)
+        } else {
+            parent_row(&self.rows, self.cursor_y, self.tab_width)
+        };
+        let Some(row) = target else {
+            self.set_status_message(if sibling { "No next line at this indentation" } else { "No enclosing line above" });
+            return;
+        };
+        self.cursor_y = row;
+        self.cursor_x = grapheme_len(leading_whitespace(&self.rows[row]));
+        self.remember_column();
+    }
+    //open the save-as prompt, prefilled with the current name if any
+    fn start_save_as(&mut self) {
+        self.save_as_mode = true;
+        self.save_as_query = match &self.absolute_path {
+            Some(absolute) => relative_path(absolute, &self.cwd).to_string_lossy().into_owned(),
+            None => self.filename.clone().unwrap_or_default(),
+        };
+    }
+    //save to the current filename and put the outcome in the status bar
+    fn save_and_report(&mut self) {
+        match self.save() {
+            Ok(()) => {
+                let name = self.filename.as_deref().unwrap_or("");
+                let msg = format!("Wrote {} lines to {}", self.rows.len(), name);
+                self.set_status_message(msg);
+            }
+            Err(e) => self.set_status_message(format!("Failed to save file: {}", e)),
+        }
+    }
+    fn process_save_as_keypress(&mut self, event: KeyEvent) {
+        if event.kind != KeyEventKind::Press {
+            return;
+        }
+        if std::mem::take(&mut self.save_as_confirm) {
+            if matches!(event.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                self.write_save_as();
+            }
+            return; // anything else goes back to editing the name
+        }
+        match event.code {
+            KeyCode::Enter => {
+                if self.save_as_query.is_empty() {
+                    return; // keep the prompt open until a name is typed
                 }
-                if i < chars.len(){
-                    i+=1;
+                let target = fs::canonicalize(self.resolve(&self.save_as_query)).ok();
+                if target.is_some() && target != self.absolute_path {
+                    self.save_as_confirm = true; // never replace some other file without asking
+                    return;
                 }
-                let quoted: String = chars[start..i].iter().collect();
-                result.push((quoted, Color::Green));
-                continue;
+                self.write_save_as();
             }
-            //Number
-            if c.is_ascii_digit() {
-                let start = i;
-                while i < chars.len() && chars[i].is_ascii_digit() {
-                    i += 1;
-                }
-                let number: String = chars[start..i].iter().collect();
-                result.push((number, Color::Magenta));
-                continue;
+            KeyCode::Backspace => {
+                self.save_as_query.pop();
             }
-
-            // Word (identifier/keyword/type)
-            if c.is_alphanumeric() || c == '_' {
-                let start = i;
-                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
-                    i += 1;
+            KeyCode::Char(c) => {
+                self.save_as_query.push(c);
+            }
+            _ => {}
+        }
+    }
+    //close the save-as prompt and write the buffer under the typed name
+    fn write_save_as(&mut self) {
+        self.save_as_mode = false;
+        let typed = std::mem::take(&mut self.save_as_query);
+        let name = self.resolve(&typed);
+        // write first, a failed save leaves the buffer with its old name, file type and read-only state
+        if let Err(e) = fs::write(&name, self.rows.join("\n")) {
+            self.set_status_message(format!("Failed to save file: {}", e));
+            return;
+        }
+        self.set_status_message(format!("Wrote {} lines to {}", self.rows.len(), typed));
+        self.set_filename(name); // a new extension may mean a new file type
+        self.dirty = false;
+    }
+    //move to the start of the previous word, or the end of the previous line at column 0
+    fn move_word_left(&mut self) {
+        if self.cursor_x == 0 {
+            if self.cursor_y > 0 {
+                self.cursor_y -= 1;
+                self.cursor_x = grapheme_len(self.rows[self.cursor_y].trim_end()); // end of its last word
+            }
+            return;
+        }
+        let Some(line) = self.rows.get(self.cursor_y) else { return };
+        let graphemes: Vec<&str> = line.graphemes(true).collect();
+        self.cursor_x = prev_word_start(&graphemes, self.cursor_x.min(graphemes.len()));
+    }
+    //move past the end of the next word, or to the first word of the next line at end of line
+    fn move_word_right(&mut self) {
+        let Some(line) = self.rows.get(self.cursor_y) else { return };
+        let graphemes: Vec<&str> = line.graphemes(true).collect();
+        let x = self.cursor_x;
+        if x >= graphemes.len() {
+            if self.cursor_y + 1 < self.rows.len() {
+                self.cursor_y += 1;
+                let next: Vec<&str> = self.rows[self.cursor_y].graphemes(true).collect();
+                self.cursor_x = next.iter().take_while(|g| word_class(g) == WordClass::Space).count();
+            }
+            return;
+        }
+        self.cursor_x = next_word_end(&graphemes, x);
+    }
+    //take the current rendered column as the one vertical movement aims for
+    fn remember_column(&mut self) {
+        let line = self.rows.get(self.cursor_y).map_or("", |l| l.as_str());
+        let mut desired_x = render_col(line, self.cursor_x, self.tab_width);
+        if self.word_wrap {
+            // with wrapping the goal is counted from the start of the cursor's screen line
+            let starts = self.wrap_starts(self.cursor_y);
+            let first_col = starts[starts.partition_point(|&start| start <= self.cursor_x) - 1];
+            desired_x -= render_col(line, first_col, self.tab_width);
+        }
+        self.desired_x = desired_x;
+    }
+    //Up/Down with word wrap: go to the screen line above or below, which can be part of the same row
+    fn move_screen_line(&mut self, down: bool) {
+        let starts = self.wrap_starts(self.cursor_y);
+        let current = starts.partition_point(|&start| start <= self.cursor_x) - 1;
+        let (row, first_col) = if down && current + 1 < starts.len() {
+            (self.cursor_y, starts[current + 1])
+        } else if down && self.cursor_y + 1 < self.rows.len() {
+            (self.cursor_y + 1, 0)
+        } else if !down && current > 0 {
+            (self.cursor_y, starts[current - 1])
+        } else if !down && self.cursor_y > 0 {
+            (self.cursor_y - 1, *self.wrap_starts(self.cursor_y - 1).last().unwrap_or(&0))
+        } else {
+            return;
+        };
+        let line = &self.rows[row];
+        let target = render_col(line, first_col, self.tab_width) + self.desired_x;
+        self.cursor_x = grapheme_at_render(line, target, self.tab_width).clamp(first_col, self.screen_line_end(row, first_col));
+        self.cursor_y = row;
+    }
+    //put the cursor on the grapheme at the goal column of the new line, or at its end when it is shorter
+    fn column_from_desired(&mut self) {
+        let line = self.rows.get(self.cursor_y).map_or("", |l| l.as_str());
+        self.cursor_x = grapheme_at_render(line, self.desired_x, self.tab_width);
+        if self.word_wrap {
+            self.cursor_x = self.cursor_x.min(self.screen_line_end(self.cursor_y, 0)); // the goal is on the first screen line
+        }
+    }
+    //buffer (row, col) under a cell of the focused pane, None outside its text; below the last line means the end of the buffer
+    fn position_at(&self, column: u16, row: u16) -> Option<(usize, usize)> {
+        let row = (row as usize).checked_sub(self.pane_extent(self.focus_top).0)?;
+        if row >= self.text_rows() {
+            return None;
+        }
+        let Some(&(file_row, first_col)) = self.screen_lines().get(row) else {
+            let last = self.rows.len().saturating_sub(1);
+            return Some((last, self.rows.get(last).map_or(0, |line| grapheme_len(line))));
+        };
+        let line = &self.rows[file_row];
+        // the gutter and the » marker are left of the first visible grapheme
+        let marker = usize::from(!self.word_wrap && self.col_offset > 0);
+        let text_col = (column as usize).saturating_sub(self.gutter_width() + marker);
+        let target = render_col(line, first_col, self.tab_width) + text_col;
+        // past the end of a wrapped screen line is still on it, not at the start of the next
+        let col = grapheme_at_render(line, target, self.tab_width).min(self.screen_line_end(file_row, first_col));
+        Some((file_row, col))
+    }
+    //click moves the cursor, dragging selects from where the button went down, the wheel scrolls the view
+    fn process_mouse_event(&mut self, event: MouseEvent) {
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left) => {
+                let (pane_top, pane_height) = self.pane_extent(self.focus_top);
+                let outside = !(pane_top..pane_top + pane_height).contains(&(event.row as usize));
+                if self.split.is_some() && outside && matches!(event.kind, MouseEventKind::Down(_)) {
+                    self.focus_other_pane(); // clicking into the other pane focuses it
                 }
-                let word: String = chars[start..i].iter().collect();
-                let color = if keywords.contains(&word.as_str()) {
-                    Color::Blue
-                } else if types.contains(&word.as_str()) {
-                    Color::Cyan
+                let Some((row, col)) = self.position_at(event.column, event.row) else {
+                    return; // the status bar is not part of the text
+                };
+                if matches!(event.kind, MouseEventKind::Down(_)) {
+                    self.selection_anchor = None;
+                } else if self.selection_anchor.is_none() {
+                    self.selection_anchor = Some((self.cursor_y, self.cursor_x)); // the cursor is still where the press was
+                }
+                self.cursor_y = row;
+                self.cursor_x = col;
+                self.remember_column();
+                self.scroll_to_cursor();
+            }
+            MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                self.row_offset = if event.kind == MouseEventKind::ScrollDown {
+                    (self.row_offset + self.scroll_speed).min(self.rows.len().saturating_sub(1))
                 } else {
-                    Color::Reset
+                    self.row_offset.saturating_sub(self.scroll_speed)
                 };
-                result.push((word, color));
-                continue;
+                // the cursor stays put unless the view left it behind
+                let last_shown = self.screen_lines().last().map_or(self.row_offset, |&(row, _)| row);
+                let cursor_y = self.cursor_y.clamp(self.row_offset, last_shown.max(self.row_offset));
+                if cursor_y != self.cursor_y {
+                    self.cursor_y = cursor_y;
+                    self.column_from_desired();
+                }
+            }
+            _ => {}
+        }
+    }
+    //remove the cursor's line and return it, the buffer always keeps at least one line to type on
+    fn remove_line(&mut self) -> String {
+        let row = self.cursor_y;
+        let line = self.rows.remove(row);
+        if self.rows.is_empty() {
+            self.rows.push(String::new());
+        }
+        self.cursor_y = self.cursor_y.min(self.rows.len() - 1);
+        self.cursor_x = self.cursor_x.min(grapheme_len(&self.rows[self.cursor_y]));
+        self.selection_anchor = None;
+        self.dirty = true;
+        line
+    }
+    //remove the graphemes from..to of the cursor's line
+    fn delete_columns(&mut self, from: usize, to: usize) {
+        let row = self.cursor_y;
+        let line = &mut self.rows[row];
+        let range = byte_index_of(line, from)..byte_index_of(line, to);
+        line.replace_range(range, "");
+        self.dirty = true;
+    }
+    //append the line below row onto it
+    fn join_next_line(&mut self, row: usize) {
+        if row + 1 < self.rows.len() {
+            let next_line = self.rows.remove(row + 1);
+            self.rows[row].push_str(&next_line);
+            self.dirty = true;
+        }
+    }
+    //show who last changed the cursor line and why, from git blame
+    fn blame_line(&mut self) {
+        if let Some(err) = &self.blame_error {
+            self.set_status_message(err.clone());
+            return;
+        }
+        if self.cursor_y >= self.rows.len() {
+            self.set_status_message("Blame: no line here");
+            return;
+        }
+        let row = self.cursor_y;
+        if self.cached_blame(row).is_none() {
+            match self.run_blame(row + 1, row + 1) {
+                Ok(blamed) => self.cache_blame(blamed),
+                Err(err) => {
+                    self.blame_error = Some(err.clone()); // not a repo, no git, untracked: none of it changes line by line
+                    self.set_status_message(err);
+                    return;
+                }
+            }
+        }
+        let info = self.cached_blame(row).unwrap_or("Blame: could not read git output").to_string();
+        self.set_status_message(info);
+    }
+    //with blame shown on every line, blame the visible ones that have nothing cached, all in one run of git
+    fn refresh_blame(&mut self) {
+        if !self.blame_visible || self.blame_error.is_some() {
+            return;
+        }
+        let Some((first, last)) = self.blame_missing() else {
+            return;
+        };
+        match self.run_blame(first, last) {
+            Ok(blamed) => self.cache_blame(blamed),
+            Err(err) => {
+                self.blame_error = Some(err.clone()); // reported once, not for every line or frame
+                self.set_status_message(err);
+            }
+        }
+    }
+    //1-based first and last of the visible lines without a cached blame, None when every one has it
+    fn blame_missing(&self) -> Option<(usize, usize)> {
+        let mut missing = self.screen_lines().into_iter().map(|(row, _)| row).filter(|&row| self.cached_blame(row).is_none());
+        let first = missing.next()?;
+        let last = missing.next_back().unwrap_or(first);
+        Some((first + 1, last + 1))
+    }
+    //run git blame for 1-based lines first..=last, feeding it the unsaved buffer so edited lines show as not committed
+    fn run_blame(&self, first: usize, last: usize) -> Result<Vec<(usize, String)>, String> {
+        let path = self.absolute_path.as_deref().ok_or("Blame: buffer has no file")?;
+        let mut command = Command::new("git");
+        command
+            .current_dir(path.parent().unwrap_or(Path::new("/")))
+            .args(["blame", "--porcelain", "-L", &format!("{},{}", first, last)]);
+        if self.dirty {
+            command.args(["--contents", "-"]);
+        }
+        command.arg("--").arg(path).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut child = command.spawn().map_err(|e| format!("Blame: could not run git: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take()
+            && self.dirty
+        {
+            let _ = stdin.write_all(self.rows.join("\n").as_bytes()); // git reports the failure if it stopped reading
+        }
+        let output = child.wait_with_output().map_err(|e| format!("Blame: {}", e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().next().unwrap_or("git blame failed");
+            return Err(format!("Blame: {}", reason.trim_start_matches("fatal: ")));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+        parse_blame(&String::from_utf8_lossy(&output.stdout), now).ok_or_else(|| "Blame: could not read git output".to_string())
+    }
+    //show a message in the status bar for a few seconds
+    fn set_status_message(&mut self, msg: impl Into<String>) {
+        self.status_message = Some((msg.into(), Instant::now()));
+    }
+    //time left before the current status message should disappear, None if there is nothing to expire
+    fn status_message_timeout(&self) -> Option<Duration> {
+        self.status_message
+            .as_ref()
+            .map(|(_, time)| STATUS_MESSAGE_TIMEOUT.saturating_sub(time.elapsed()))
+    }
+    //time until the next autosave, None while there is nothing it would write
+    fn autosave_timeout(&self) -> Option<Duration> {
+        let interval = self.autosave_interval?;
+        self.buffers.iter().any(Buffer::autosaves).then(|| interval.saturating_sub(self.last_autosave.elapsed()))
+    }
+    //once the interval has passed, write every modified file that has a name and may be written
+    fn autosave_if_due(&mut self) {
+        if self.autosave_timeout().is_none_or(|left| !left.is_zero()) {
+            return;
+        }
+        self.last_autosave = Instant::now();
+        let mut failed = Vec::new();
+        for buffer in self.buffers.iter_mut().filter(|buffer| buffer.autosaves()) {
+            if let Err(err) = buffer.save() {
+                failed.push(format!("{}: {}", buffer.filename.as_deref().unwrap_or_default(), err));
+            }
+        }
+        if !failed.is_empty() {
+            self.set_status_message(format!("Autosave failed for {}", failed.join(", ")));
+        }
+    }
+}
+
+// Turn `git blame --porcelain` output into (1-based line, "a1b2c3d alice, 3 weeks ago: summary") for each line;
+// a commit's headers only follow the first line from it, later ones reuse them
+fn parse_blame(porcelain: &str, now: i64) -> Option<Vec<(usize, String)>> {
+    let mut commits: HashMap<&str, (&str, Option<i64>, &str)> = HashMap::new(); // author, time, summary
+    let mut current = None; // (sha, line) whose headers are being read
+    let mut blamed = Vec::new();
+    for line in porcelain.lines() {
+        if line.starts_with('\t') {
+            // headers end where the line's content starts
+            let (sha, number): (&str, usize) = current.take()?;
+            let info = if sha.bytes().all(|b| b == b'0') {
+                "not committed".to_string() // git's placeholder commit for changes not yet committed
+            } else {
+                let (author, time, summary) = commits.get(sha).copied().unwrap_or_default();
+                format!("{} {}, {}: {}", &sha[..7], author, relative_time(now - time?), summary)
+            };
+            blamed.push((number, info));
+        } else if let Some((sha, _)) = current {
+            let (author, time, summary) = commits.entry(sha).or_default();
+            if let Some(value) = line.strip_prefix("author ") {
+                *author = value;
+            } else if let Some(value) = line.strip_prefix("author-time ") {
+                *time = value.parse::<i64>().ok();
+            } else if let Some(value) = line.strip_prefix("summary ") {
+                *summary = value;
             }
+        } else {
+            // "<sha> <line in the commit> <line now> [<lines in the group>]"
+            let mut fields = line.split(' ');
+            let sha = fields.next().filter(|sha| sha.len() >= 7)?;
+            current = Some((sha, fields.nth(1)?.parse().ok().filter(|&n| n > 0)?));
+        }
+    }
+    Some(blamed)
+}
 
-            // Any other single char
-            result.push((c.to_string(), Color::Reset));
-            i += 1;
+// Describe an age in seconds the way people say it, using the largest whole unit
+fn relative_time(secs: i64) -> String {
+    const UNITS: [(&str, i64); 6] = [
+        ("year", 365 * 86400),
+        ("month", 30 * 86400),
+        ("week", 7 * 86400),
+        ("day", 86400),
+        ("hour", 3600),
+        ("minute", 60),
+    ];
+    for (unit, len) in UNITS {
+        if secs >= len {
+            let n = secs / len;
+            return format!("{} {}{} ago", n, unit, if n == 1 { "" } else { "s" });
         }
+    }
+    "just now".to_string()
+}
 
-        result
+// Push an undo or redo step, dropping the oldest once the stack holds more than MAX_UNDO_DEPTH
+fn push_capped(stack: &mut VecDeque<UndoStep>, step: UndoStep) {
+    stack.push_back(step);
+    if stack.len() > MAX_UNDO_DEPTH {
+        stack.pop_front();
     }
-    //save state
-    fn snapshot(&self) -> EditorState {
-        EditorState {
-            buffer: self.rows.clone(),
-            cursor_x: self.cursor_x,
-            cursor_y: self.cursor_y,
+}
+
+// Index of the grapheme drawn at rendered column `target`, or the line's length when the line ends first
+fn grapheme_at_render(line: &str, target: usize, tab_width: usize) -> usize {
+    let mut at = 0;
+    line.graphemes(true)
+        .take_while(|g| {
+            at += grapheme_width(g, at, tab_width);
+            at <= target
+        })
+        .count()
+}
+
+// Convert a grapheme index within a line (what cursor_x counts) into a byte offset for String methods
+fn byte_index_of(line: &str, idx: usize) -> usize {
+    line.grapheme_indices(true).nth(idx).map_or(line.len(), |(i, _)| i)
+}
+
+// Length of a line in grapheme clusters, the unit cursor_x and col_offset are measured in
+fn grapheme_len(line: &str) -> usize {
+    line.graphemes(true).count()
+}
+
+// Screen cells a grapheme takes when it starts at render column at: tabs reach the next tab stop,
+// CJK and emoji take two, combining marks ride along with the character they follow
+fn grapheme_width(g: &str, at: usize, tab_width: usize) -> usize {
+    if g == "\t" {
+        return tab_width - at % tab_width;
+    }
+    if g.chars().next().is_some_and(is_wide) { 2 } else { 1 }
+}
+
+// Whether the terminal draws a character two cells wide: CJK ideographs, kana, Hangul, fullwidth forms and emoji
+fn is_wide(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6 | 0x1F300..=0x1F64F | 0x1F900..=0x1F9FF | 0x20000..=0x3FFFD
+    )
+}
+
+// Rendered column of grapheme index idx, expanding the tabs before it
+fn render_col(line: &str, idx: usize, tab_width: usize) -> usize {
+    line.graphemes(true).take(idx).fold(0, |at, g| at + grapheme_width(g, at, tab_width))
+}
+
+// Result of evaluating an arithmetic expression
+#[derive(Clone, Copy, Debug)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(n) => n as f64,
+            Number::Float(f) => f,
         }
     }
-    //saves rows from buffer
-    fn restore(&mut self, state: EditorState) {
-        self.rows = state.buffer;
-        self.cursor_x = state.cursor_x;
-        self.cursor_y = state.cursor_y;
+}
+
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Number::Int(n) => write!(f, "{}", n),
+            Number::Float(x) => write!(f, "{}", x),
+        }
     }
-    fn push_undo(&mut self) {
-        self.undo_stack.push(self.snapshot());
-        self.redo_stack.clear(); // Clear redo history on new edit
+}
+
+// Recursive-descent evaluator for + - * / % and parentheses over ints, floats and 0x/0b literals
+struct ExprParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
     }
-    //start search prompt
-    fn start_search(&mut self) {
-        self.search_mode = true;
-        self.search_query.clear();
-        self.search_results.clear();
-        self.current_match = 0;
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Number, String> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = apply(op, value, rhs)?;
+        }
+        Ok(value)
     }
-    //search rows for query and keep it in search_results
-    fn perform_search(&mut self){
-        self.search_results.clear();
-        if self.search_query.is_empty() {
-            return;
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<Number, String> {
+        let mut value = self.unary()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            value = apply(op, value, rhs)?;
         }
-        let q = self.search_query.to_lowercase();
-        for (i, line) in self.rows.iter().enumerate(){
-            let line_lower = line.to_lowercase();
-            let mut start = 0;
-            while let Some(pos) = line_lower[start..].find(&q){
-                self.search_results.push((i,start+pos)); //push into search_results if found
-                start += pos+1; // continue searching
+        Ok(value)
+    }
+    // unary := ('-' | '+') unary | primary
+    fn unary(&mut self) -> Result<Number, String> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                match self.unary()? {
+                    Number::Int(n) => n.checked_neg().map(Number::Int).ok_or_else(|| "overflow".to_string()),
+                    Number::Float(f) => Ok(Number::Float(-f)),
+                }
             }
+            Some('+') => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.primary(),
         }
-        self.current_match = 0;
-        if let Some(&(row,col)) = self.search_results.get(0){
-            self.cursor_x = row;
-            self.cursor_y = col;
-            self.scroll_to_cursor();
+    }
+    // primary := number | '(' expr ')'
+    fn primary(&mut self) -> Result<Number, String> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.expr()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) => Err(format!("unexpected '{}'", c)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+    fn number(&mut self) -> Result<Number, String> {
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '.' || *c == '_') {
+            self.pos += 1;
+        }
+        let literal: String = self.chars[start..self.pos].iter().filter(|&&c| c != '_').collect();
+        let radix = match literal.get(..2) {
+            Some("0x") | Some("0X") => Some(16),
+            Some("0b") | Some("0B") => Some(2),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            return i64::from_str_radix(&literal[2..], radix)
+                .map(Number::Int)
+                .map_err(|_| format!("bad number '{}'", literal));
+        }
+        if let Ok(n) = literal.parse::<i64>() {
+            return Ok(Number::Int(n));
+        }
+        if literal.chars().all(|c| c.is_ascii_digit()) {
+            return Err("overflow".to_string()); // digits only, so too big for an integer
+        }
+        literal.parse::<f64>().map(Number::Float).map_err(|_| format!("bad number '{}'", literal))
+    }
+}
+
+// Apply a binary operator, staying in integers while the result is exact
+fn apply(op: char, lhs: Number, rhs: Number) -> Result<Number, String> {
+    if let (Number::Int(a), Number::Int(b)) = (lhs, rhs) {
+        let result = match op {
+            '+' => a.checked_add(b),
+            '-' => a.checked_sub(b),
+            '*' => a.checked_mul(b),
+            '/' if b == 0 => return Err("division by zero".to_string()),
+            '/' if a % b != 0 => return Ok(Number::Float(a as f64 / b as f64)),
+            '/' => a.checked_div(b),
+            '%' if b == 0 => return Err("division by zero".to_string()),
+            _ => a.checked_rem(b),
+        };
+        return result.map(Number::Int).ok_or_else(|| "overflow".to_string());
+    }
+    let (a, b) = (lhs.as_f64(), rhs.as_f64());
+    if matches!(op, '/' | '%') && b == 0.0 {
+        return Err("division by zero".to_string());
+    }
+    let result = match op {
+        '+' => a + b,
+        '-' => a - b,
+        '*' => a * b,
+        '/' => a / b,
+        _ => a % b,
+    };
+    if result.is_finite() { Ok(Number::Float(result)) } else { Err("overflow".to_string()) }
+}
+
+// Evaluate a whole arithmetic expression, anything left over after it is an error
+fn evaluate(input: &str) -> Result<Number, String> {
+    let mut parser = ExprParser { chars: input.chars().collect(), pos: 0 };
+    let value = parser.expr()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected '{}'", c)),
+    }
+}
+
+// Standard base64 with padding, for the OSC 52 clipboard sequence
+#[cfg(not(any(feature = "system-clipboard", test)))]
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
         }
     }
+    out
+}
+
+// Shortest readable form of an absolute path: relative to cwd when inside it, with the home directory as ~
+fn display_path(absolute: &Path, cwd: &Path, home: Option<&Path>, policy: PathDisplay) -> String {
+    let mut shown = absolute.display().to_string();
+    if let Some(rest) = home.and_then(|home| absolute.strip_prefix(home).ok()) {
+        shown = Path::new("~").join(rest).display().to_string();
+    }
+    let relative = relative_path(absolute, cwd).display().to_string();
+    match policy {
+        PathDisplay::Absolute => shown,
+        PathDisplay::Relative => relative,
+        // on a tie the absolute form, it does not change meaning with the cwd
+        PathDisplay::Shortest if relative.len() < shown.len() => relative,
+        PathDisplay::Shortest => shown,
+    }
+}
+
+// `absolute` from `cwd`, both canonical, going up with .. as far as they differ; . for the cwd itself
+fn relative_path(absolute: &Path, cwd: &Path) -> PathBuf {
+    let common = absolute.components().zip(cwd.components()).take_while(|(a, b)| a == b).count();
+    let mut relative: PathBuf = cwd.components().skip(common).map(|_| "..").collect();
+    relative.extend(absolute.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+// Parse go-to-line input, "42" or "42:7", into a 1-based (line, col); None for empty, non-numeric or zero
+fn parse_goto(input: &str) -> Option<(usize, usize)> {
+    let (line, col) = match input.trim().split_once(':') {
+        Some((line, col)) => (line, Some(col)),
+        None => (input.trim(), None),
+    };
+    let line: usize = line.trim().parse().ok().filter(|&n| n > 0)?;
+    let col = match col {
+        Some(col) => col.trim().parse().ok().filter(|&n| n > 0)?,
+        None => 1,
+    };
+    Some((line, col))
+}
+
+// Rendered width of a line's indentation, None for a blank line, which belongs to whatever block it is in
+fn indent_width(line: &str, tab_width: usize) -> Option<usize> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    Some(render_col(line, grapheme_len(leading_whitespace(line)), tab_width))
+}
 
-    fn scroll_to_cursor(&mut self) {
-        let screen_cols = self.screen_cols as usize;
-        if self.cursor_x < self.col_offset {
-            self.col_offset = self.cursor_x;
-        } else if self.cursor_x >= self.col_offset + screen_cols {
-            self.col_offset = self.cursor_x - screen_cols + 1;
+// The nearest row above with less indentation than row
+fn parent_row(rows: &[String], row: usize, tab_width: usize) -> Option<usize> {
+    let indent = indent_width(rows.get(row)?, tab_width)?;
+    (0..row).rev().find(|&above| indent_width(&rows[above], tab_width).is_some_and(|width| width < indent))
+}
+
+// Last row of the block row opens: the deeper indented rows after it, blank rows between them included
+fn block_end(rows: &[String], row: usize, tab_width: usize) -> usize {
+    let Some(indent) = rows.get(row).and_then(|line| indent_width(line, tab_width)) else {
+        return row;
+    };
+    let mut end = row;
+    for (below, line) in rows.iter().enumerate().skip(row + 1) {
+        match indent_width(line, tab_width) {
+            Some(width) if width <= indent => break,
+            Some(_) => end = below,
+            None => {}
         }
     }
-    fn draw_search_prompt(&self, stdout: &mut io::Stdout) -> std::io::Result<()> {
-        use crossterm::style::{SetAttribute, Attribute, SetBackgroundColor, SetForegroundColor, Color};
-        execute!(
-            stdout,
-            cursor::MoveTo(0, self.screen_rows - 1),
-            Clear(ClearType::CurrentLine),
-            SetBackgroundColor(Color::Black),
-            SetForegroundColor(Color::Yellow),
-            SetAttribute(Attribute::Bold),
-            Print(format!("Search: {}", self.search_query)),
-            SetAttribute(Attribute::Reset),
-            SetForegroundColor(Color::Reset),
-            SetBackgroundColor(Color::Reset),
-        )
+    end
+}
+
+// The row after row's block at the same indentation, none when the enclosing block ends first
+fn next_sibling_row(rows: &[String], row: usize, tab_width: usize) -> Option<usize> {
+    let indent = indent_width(rows.get(row)?, tab_width)?;
+    let next = (block_end(rows, row, tab_width) + 1..rows.len()).find(|&below| !rows[below].trim().is_empty())?;
+    (indent_width(&rows[next], tab_width) == Some(indent)).then_some(next)
+}
+
+// Whether the key changes the text in the editing keymap, these are refused in a read-only buffer
+fn edits_buffer(event: &KeyEvent) -> bool {
+    let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = event.modifiers.contains(KeyModifiers::ALT);
+    match event.code {
+        KeyCode::Char(c) if ctrl && !alt => matches!(c.to_ascii_lowercase(), 'z' | 'x' | 'y' | 'v' | 'd' | 'k'),
+        KeyCode::Char(c) if alt && !ctrl => matches!(c, 'x' | 'v' | 'V' | 'd' | 'D' | 'e' | '='),
+        KeyCode::Char(_) | KeyCode::Backspace | KeyCode::Delete | KeyCode::Enter => true, // AltGr text too
+        KeyCode::Tab | KeyCode::BackTab => !alt, // Alt+Tab only switches buffers
+        _ => false,
     }
-    fn process_search_keypress(&mut self, event: KeyEvent) -> bool {
-        if event.kind != KeyEventKind::Press {
-            return false;
-        }
-        match event.code {
-            KeyCode::Esc => {
-                self.search_mode = false;
-                self.search_query.clear();
-                self.search_results.clear();
-                return false;
-            }
-            KeyCode::Enter => {
-                if self.search_results.is_empty() {
-                    return false;
-                }
-                // Go to next match
-                self.current_match = (self.current_match + 1) % self.search_results.len();
-                let (row, col) = self.search_results[self.current_match];
-                self.cursor_y = row;
-                self.cursor_x = col;
-                self.scroll_to_cursor();
-            }
-            KeyCode::Backspace => {
-                self.search_query.pop();
-                self.perform_search();
-            }
-            KeyCode::Char(c) => {
-                self.search_query.push(c);
-                self.perform_search();
-            }
-            _ => {}
+}
+
+// The run of spaces and tabs a line starts with
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+// Word characters are the same as identifiers in highlight_line: alphanumerics and '_'
+#[derive(PartialEq)]
+enum WordClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+// Word motion treats identifier characters and runs of punctuation as separate words
+fn word_class(g: &str) -> WordClass {
+    match g.chars().next() {
+        Some(c) if c.is_alphanumeric() || c == '_' => WordClass::Word,
+        Some(c) if !c.is_whitespace() => WordClass::Punctuation,
+        _ => WordClass::Space,
+    }
+}
+
+// Start of the word before column x, skipping any whitespace in between
+fn prev_word_start(graphemes: &[&str], mut x: usize) -> usize {
+    while x > 0 && word_class(graphemes[x - 1]) == WordClass::Space {
+        x -= 1;
+    }
+    if let Some(class) = x.checked_sub(1).map(|i| word_class(graphemes[i])) {
+        while x > 0 && word_class(graphemes[x - 1]) == class {
+            x -= 1;
         }
-        false
     }
+    x
+}
 
+// End of the word after column x, skipping any whitespace in between
+fn next_word_end(graphemes: &[&str], mut x: usize) -> usize {
+    while x < graphemes.len() && word_class(graphemes[x]) == WordClass::Space {
+        x += 1;
+    }
+    if let Some(class) = graphemes.get(x).map(|g| word_class(g)) {
+        while x < graphemes.len() && word_class(graphemes[x]) == class {
+            x += 1;
+        }
+    }
+    x
+}
 
+// Print a run of text in its syntax color, with any selection or search emphasis on top
+fn print_styled(stdout: &mut impl Write, text: &str, (color, emphasis): (Color, Emphasis), line_bg: Option<Color>, theme: &Theme) -> std::io::Result<()> {
+    if let Some(bg) = line_bg
+        && emphasis != Emphasis::None {
+        print_styled(stdout, text, (color, emphasis), None, theme)?;
+        return execute!(stdout, SetBackgroundColor(bg)); // emphasis resets the background, put the line's back
+    }
+    match emphasis {
+        Emphasis::Selection if theme.selection_bg == Color::Reset => execute!(stdout, Print(text.with(color).reverse())), // inverted colors
+        Emphasis::Selection => execute!(stdout, Print(text.with(color).on(theme.selection_bg))),
+        Emphasis::CurrentMatch => execute!(stdout, Print(text.with(theme.search_fg).on(theme.search_active_bg))),
+        Emphasis::Match => execute!(stdout, Print(text.with(theme.search_fg).on(theme.search_inactive_bg))),
+        Emphasis::Bracket => execute!(stdout, Print(text.with(color).on(theme.bracket_bg))),
+        Emphasis::TrailingWhitespace => execute!(stdout, Print(text.on(theme.warning_bg))),
+        Emphasis::OverLimit => execute!(stdout, Print(text.with(color).on(theme.warning_bg))),
+        Emphasis::None => execute!(stdout, Print(text.with(color))),
+    }
 }
 
 // Entry point for the program
@@ -468,33 +4022,98 @@ fn main() -> std::io::Result<()> {
     execute!(
         stdout,
         EnterAlternateScreen,
-        EnableMouseCapture,
         cursor::SetCursorStyle::BlinkingBar,
     )?;
-    //Initialization
-    let mut editor = Editor::new()?;
-    //Get filename
-    if let Some(file) = env::args().nth(1){
-        if Path::new(&file).exists() {
-            editor.open(&file)?;
+    //Initialization, command line flags below win over the config
+    let (config, mut startup_errors) = Config::load();
+    let mut editor = Editor::new(&config)?;
+    // --view, or running the binary as `rpager`, opens the file in the read-only pager
+    let program = env::args().next().unwrap_or_default();
+    editor.view_mode = Path::new(&program).file_stem().is_some_and(|stem| stem == "rpager");
+    let mut files = Vec::new();
+    let mut theme = config.theme.clone(); // a name or a theme file
+    let mut readonly = false; // --readonly opens every file given on the command line read-only
+    for arg in env::args().skip(1) {
+        if arg == "--view" {
+            editor.view_mode = true;
+        } else if arg == "--readonly" {
+            readonly = true;
+        } else if arg == "--no-mouse" {
+            editor.mouse_capture = false;
+        } else if let Some(lines) = arg.strip_prefix("--scroll-speed=") {
+            editor.scroll_speed = lines.parse().unwrap_or(editor.scroll_speed).max(1);
+        } else if let Some(name) = arg.strip_prefix("--theme=") {
+            theme = Some(name.to_string());
+        } else {
+            files.push(arg);
+        }
+    }
+    if let Some(name) = theme
+        && let Err(err) = editor.use_theme(&name) {
+        startup_errors.push(format!("Theme {} not loaded: {}", name, err)); // start anyway, in the default colors
+    }
+    //Get filename, every further one opens in a buffer of its own behind the first,
+    //one that cannot be read is reported in the status bar and the rest still open
+    for (i, file) in files.into_iter().enumerate() {
+        let opened = if i > 0 {
+            editor.open_buffer(&file)
+        } else if Path::new(&file).exists() {
+            editor.open(&file)
         } else {
-            editor.filename = Some(file);
+            editor.set_filename(file.clone());
+            Ok(())
+        };
+        match opened {
+            Ok(()) => editor.readonly |= readonly,
+            Err(err) => startup_errors.push(format!("Failed to open {}: {}", file, err)),
         }
     }
+    editor.switch_buffer(0);
+    if !startup_errors.is_empty() {
+        editor.set_status_message(startup_errors.join("; "));
+    }
+    let mut mouse_captured = false;
     // Main input loop
     loop {
-        editor.refresh_screen(&mut stdout)?; // Redraw screen
-        // Handling inputs
-        if let Event::Key(key_event) = event::read()? {
-            if editor.search_mode {
-                editor.process_search_keypress(key_event);
+        if editor.mouse_capture != mouse_captured {
+            // Alt+m or --no-mouse hands the mouse back to the terminal for its own selection
+            if editor.mouse_capture {
+                execute!(stdout, EnableMouseCapture)?;
             } else {
-                if key_event.code == KeyCode::Char('f') && key_event.modifiers.contains(KeyModifiers::ALT){
-                    editor.start_search();
-                } else if editor.process_keypress(key_event){
-                    break;
-                }
+                execute!(stdout, DisableMouseCapture)?;
+            }
+            mouse_captured = editor.mouse_capture;
+        }
+        editor.autosave_if_due();
+        editor.follow_file();
+        editor.refresh_blame(); // lines scrolled into view or edited since they were blamed
+        editor.recompute_highlight_state(); // the last key may have opened or closed a comment or string
+        editor.refresh_screen(&mut stdout)?; // Redraw screen
+        // Wake up when a status message expires so it disappears, an autosave is due or a followed file may have grown,
+        // without waiting for a keypress
+        let follow = editor.follow.then_some(FOLLOW_INTERVAL); // time to look for appended lines again
+        let wake = [editor.status_message_timeout(), editor.autosave_timeout(), follow].into_iter().flatten().min();
+        if let Some(timeout) = wake
+            && !event::poll(timeout)? {
+            if editor.status_message_timeout().is_some_and(|left| left.is_zero()) {
+                editor.status_message = None;
             }
+            continue;
+        }
+        // Handling inputs
+        let event = event::read()?;
+        if let Event::Resize(cols, rows) = event {
+            editor.resize(cols, rows);
+        }
+        // prompts keep the keyboard to themselves, the mouse only acts on the text
+        if let Event::Mouse(mouse_event) = event
+            && !editor.prompt_open()
+        {
+            editor.process_mouse_event(mouse_event);
+        }
+        if let Event::Key(key_event) = event
+            && editor.handle_key(key_event) {
+            break;
         }
     }
     //restore normal terminal mode
@@ -508,3 +4127,986 @@ fn main() -> std::io::Result<()> {
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an editor on an 80x24 screen holding lines, with the cursor at the start
+    fn editor_with(lines: &[&str]) -> Editor {
+        let mut editor = Editor::with_size(&Config::default(), 80, 24);
+        editor.rows = lines.iter().map(|line| line.to_string()).collect();
+        editor
+    }
+
+    // press a key, without the debounce that would swallow a repeated one
+    fn press(editor: &mut Editor, code: KeyCode, modifiers: KeyModifiers) {
+        editor.last_key = None;
+        editor.handle_key(KeyEvent::new(code, modifiers));
+    }
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            press(editor, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    const YAML: &str = "server:\n  host: example.org\n  ports:\n    - 80\n\n    - 443\n  tls: true\nclient:\n  retries: 3\n    # deeper to the end";
+
+    const PYTHON: &str = "class Greeter:\n\tdef greet(self):\n        print('hi')\n\n\tdef leave(self):\n\t\tpass\n";
+
+    #[test]
+    fn outline_parent_is_the_nearest_shallower_line() {
+        let rows = lines(YAML);
+        assert_eq!(parent_row(&rows, 3, 4), Some(2)); // - 80 is under ports
+        assert_eq!(parent_row(&rows, 5, 4), Some(2)); // the blank line between does not matter
+        assert_eq!(parent_row(&rows, 6, 4), Some(0));
+        assert_eq!(parent_row(&rows, 0, 4), None);
+        assert_eq!(parent_row(&rows, 4, 4), None); // a blank line has no depth of its own
+    }
+
+    #[test]
+    fn outline_sibling_skips_deeper_lines_and_stops_at_the_parent_end() {
+        let rows = lines(YAML);
+        assert_eq!(next_sibling_row(&rows, 0, 4), Some(7));
+        assert_eq!(next_sibling_row(&rows, 2, 4), Some(6)); // over the list, blank line included
+        assert_eq!(next_sibling_row(&rows, 6, 4), None); // server: ends before another key
+        assert_eq!(next_sibling_row(&rows, 7, 4), None);
+    }
+
+    #[test]
+    fn outline_block_keeps_inner_blank_lines_and_can_end_at_eof() {
+        let rows = lines(YAML);
+        assert_eq!(block_end(&rows, 2, 4), 5);
+        assert_eq!(block_end(&rows, 0, 4), 6);
+        assert_eq!(block_end(&rows, 7, 4), 9); // runs to the last line of the file
+        assert_eq!(block_end(&rows, 9, 4), 9);
+    }
+
+    #[test]
+    fn outline_measures_tabs_by_their_rendered_width() {
+        let rows = lines(PYTHON);
+        // a tab and eight spaces are different depths with tab_width 4, the same with tab_width 8
+        assert_eq!(block_end(&rows, 1, 4), 2);
+        assert_eq!(parent_row(&rows, 2, 4), Some(1));
+        assert_eq!(next_sibling_row(&rows, 1, 4), Some(4));
+        assert_eq!(parent_row(&rows, 2, 8), Some(0));
+        assert_eq!(next_sibling_row(&rows, 4, 4), None);
+        assert_eq!(block_end(&rows, 0, 4), 5);
+        assert_eq!(block_end(&rows, 4, 4), 5); // the class ends with the file
+    }
+
+    fn eval(input: &str) -> Result<String, String> {
+        evaluate(input).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn evaluate_follows_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok("7".to_string()));
+        assert_eq!(eval("(1 + 2) * 3"), Ok("9".to_string()));
+        assert_eq!(eval("10 - 4 - 3"), Ok("3".to_string())); // left to right
+        assert_eq!(eval("-2 * -3 + 7 % 4"), Ok("9".to_string()));
+        assert_eq!(eval("0x10 + 0b11"), Ok("19".to_string()));
+        assert_eq!(eval("7 / 2"), Ok("3.5".to_string())); // inexact division leaves the integers
+        assert_eq!(eval("1.5 * 2"), Ok("3".to_string()));
+    }
+
+    #[test]
+    fn evaluate_reports_division_by_zero() {
+        assert_eq!(eval("1 / 0"), Err("division by zero".to_string()));
+        assert_eq!(eval("5 % (2 - 2)"), Err("division by zero".to_string()));
+        assert_eq!(eval("1.5 / 0"), Err("division by zero".to_string()));
+    }
+
+    #[test]
+    fn evaluate_rejects_malformed_input() {
+        assert_eq!(eval("1 +"), Err("unexpected end of expression".to_string()));
+        assert_eq!(eval("(1 + 2"), Err("missing ')'".to_string()));
+        assert_eq!(eval("1 2"), Err("unexpected '2'".to_string()));
+        assert_eq!(eval("2 * x"), Err("unexpected 'x'".to_string()));
+        assert_eq!(eval("0xzz"), Err("bad number '0xzz'".to_string()));
+        assert_eq!(eval("99999999999999999999"), Err("overflow".to_string()));
+        assert_eq!(eval(""), Err("unexpected end of expression".to_string()));
+    }
+
+    // select `len` graphemes to the right of the cursor
+    fn select_right(editor: &mut Editor, len: usize) {
+        for _ in 0..len {
+            press(editor, KeyCode::Right, KeyModifiers::SHIFT);
+        }
+    }
+
+    fn undo(editor: &mut Editor) {
+        press(editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+    }
+
+    #[test]
+    fn alt_e_replaces_the_expression_in_one_undo_step() {
+        let mut editor = editor_with(&["total 2*(3+4) items"]);
+        editor.cursor_x = 9; // inside the word
+        press(&mut editor, KeyCode::Char('e'), KeyModifiers::ALT);
+        assert_eq!(editor.rows, ["total 14 items"]);
+        assert_eq!((editor.cursor_x, editor.selection_anchor), (8, None));
+        undo(&mut editor);
+        assert_eq!(editor.rows, ["total 2*(3+4) items"]);
+        assert!(editor.undo_stack.is_empty());
+
+        let mut editor = editor_with(&["x = 1 + 2 * 3;"]);
+        editor.cursor_x = 4;
+        select_right(&mut editor, 9);
+        press(&mut editor, KeyCode::Char('e'), KeyModifiers::ALT);
+        assert_eq!(editor.rows, ["x = 7;"]);
+        undo(&mut editor);
+        assert_eq!(editor.rows, ["x = 1 + 2 * 3;"]);
+        assert!(editor.undo_stack.is_empty());
+    }
+
+    #[test]
+    fn alt_equals_appends_the_value_in_one_undo_step() {
+        let mut editor = editor_with(&["7/2"]);
+        editor.cursor_x = 3; // just after the word
+        press(&mut editor, KeyCode::Char('='), KeyModifiers::ALT);
+        assert_eq!(editor.rows, ["7/2 = 3.5"]);
+        assert_eq!(editor.cursor_x, 9);
+        undo(&mut editor);
+        assert_eq!(editor.rows, ["7/2"]);
+        assert!(editor.undo_stack.is_empty());
+
+        let mut editor = editor_with(&["sum: 0x10 + 1 bytes"]);
+        editor.cursor_x = 5;
+        select_right(&mut editor, 8);
+        press(&mut editor, KeyCode::Char('='), KeyModifiers::ALT);
+        assert_eq!(editor.rows, ["sum: 0x10 + 1 = 17 bytes"]);
+        assert_eq!(editor.selection_anchor, None);
+        undo(&mut editor);
+        assert_eq!(editor.rows, ["sum: 0x10 + 1 bytes"]);
+        assert!(editor.undo_stack.is_empty());
+    }
+
+    #[test]
+    fn failed_evaluation_leaves_cursor_and_selection_alone() {
+        let mut editor = editor_with(&["let x = 1+;"]);
+        editor.cursor_x = 9;
+        press(&mut editor, KeyCode::Char('e'), KeyModifiers::ALT);
+        assert_eq!((editor.cursor_x, editor.selection_anchor), (9, None));
+        type_text(&mut editor, "2");
+        assert_eq!(editor.rows, ["let x = 12+;"]); // typed, not replacing the word
+        press(&mut editor, KeyCode::Char('e'), KeyModifiers::ALT);
+        assert_eq!(editor.rows, ["let x = 12+;"]);
+    }
+
+    #[test]
+    fn failed_save_as_keeps_the_old_name() {
+        let mut editor = editor_with(&["fn main() {}"]);
+        editor.set_filename("main.rs".to_string());
+        editor.readonly = true;
+        editor.save_as_query = "/nonexistent-directory/main.py".to_string();
+        editor.write_save_as();
+        assert_eq!(editor.filename.as_deref(), Some("main.rs"));
+        assert!(editor.language == Language::Rust && editor.readonly);
+
+        let path = env::temp_dir().join(format!("rust_editor_save_as_{}.py", std::process::id()));
+        editor.save_as_query = path.to_string_lossy().into_owned();
+        editor.dirty = true;
+        editor.write_save_as();
+        let written = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(written.ok().as_deref(), Some("fn main() {}"));
+        assert!(editor.language == Language::Python && !editor.readonly && !editor.dirty);
+    }
+
+    #[test]
+    fn grapheme_helpers_count_clusters_not_bytes() {
+        let line = "he\u{301}llo 世界"; // the é is an e with a combining accent
+        assert_eq!(grapheme_len(line), 8);
+        assert_eq!(byte_index_of(line, 1), 1);
+        assert_eq!(byte_index_of(line, 2), 4); // past both code points of the é
+        assert_eq!(byte_index_of(line, 7), 11); // 世 takes three bytes
+        assert_eq!(byte_index_of(line, 8), line.len());
+        assert_eq!(byte_index_of(line, 20), line.len()); // past the end clamps
+    }
+
+    #[test]
+    fn editing_multi_byte_text_moves_by_grapheme() {
+        let mut editor = editor_with(&["héllo 世界"]);
+        press(&mut editor, KeyCode::End, KeyModifiers::NONE);
+        assert_eq!(editor.cursor_x, 8);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.rows, ["héllo 世"]);
+        press(&mut editor, KeyCode::Home, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Right, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Right, KeyModifiers::NONE);
+        assert_eq!(editor.cursor_x, 2);
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.rows, ["hllo 世"]);
+        type_text(&mut editor, "日");
+        press(&mut editor, KeyCode::Delete, KeyModifiers::NONE);
+        assert_eq!(editor.rows, ["h日lo 世"]);
+        press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(editor.rows, ["h日", "lo 世"]);
+
+        // a combining mark typed after a letter joins it, Backspace then removes the pair
+        let mut editor = editor_with(&["e"]);
+        editor.cursor_x = 1;
+        type_text(&mut editor, "\u{301}");
+        assert_eq!((editor.rows[0].as_str(), editor.cursor_x), ("e\u{301}", 1));
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.rows, [""]);
+    }
+
+    #[test]
+    fn render_col_expands_tabs_to_the_next_stop() {
+        let line = "\tab\tc";
+        assert_eq!(render_col(line, 0, 4), 0);
+        assert_eq!(render_col(line, 1, 4), 4);
+        assert_eq!(render_col(line, 3, 4), 6);
+        assert_eq!(render_col(line, 4, 4), 8); // the tab after "ab" fills only to the stop
+        assert_eq!(render_col(line, 5, 4), 9);
+        assert_eq!(render_col(line, 3, 8), 10);
+        assert_eq!(render_col("abcd\t", 5, 4), 8); // a tab on a stop is a whole stop wide
+    }
+
+    #[test]
+    fn render_col_counts_wide_and_combining_characters() {
+        let line = "a世界e\u{301}\tb";
+        assert_eq!(render_col(line, 1, 4), 1);
+        assert_eq!(render_col(line, 2, 4), 3);
+        assert_eq!(render_col(line, 3, 4), 5);
+        assert_eq!(render_col(line, 4, 4), 6); // é is one cell, accent and all
+        assert_eq!(render_col(line, 5, 4), 8);
+        assert_eq!(render_col("🦀x", 1, 4), 2);
+    }
+
+    #[test]
+    fn grapheme_at_render_is_the_inverse_of_render_col() {
+        let line = "\ta世e\u{301}\tb";
+        for idx in 0..=grapheme_len(line) {
+            assert_eq!(grapheme_at_render(line, render_col(line, idx, 4), 4), idx);
+        }
+        // a column inside a tab or a wide character lands on it
+        assert_eq!(grapheme_at_render(line, 2, 4), 0);
+        assert_eq!(grapheme_at_render(line, 6, 4), 2);
+        assert_eq!(grapheme_at_render(line, 100, 4), grapheme_len(line)); // past the end
+    }
+
+    const BLAME: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f9012345678 12 12 1
+author Alice Example
+author-mail <alice@example.org>
+author-time 1700000000
+author-tz +0100
+committer Alice Example
+committer-mail <alice@example.org>
+committer-time 1700000000
+committer-tz +0100
+summary Fix off-by-one in scroll clamp
+previous 0123456789abcdef0123456789abcdef01234567 src/main.rs
+filename src/main.rs
+\tlet end = start + len;
+";
+
+    // the summary of the one line blamed in porcelain
+    fn blame_of_line(porcelain: &str, now: i64) -> Option<String> {
+        parse_blame(porcelain, now)?.into_iter().next().map(|(_, info)| info)
+    }
+
+    #[test]
+    fn parse_blame_reads_a_committed_line() {
+        let three_weeks = 21 * 86400;
+        assert_eq!(
+            blame_of_line(BLAME, 1_700_000_000 + three_weeks).as_deref(),
+            Some("a1b2c3d Alice Example, 3 weeks ago: Fix off-by-one in scroll clamp"),
+        );
+        // a header-like text in the line's own content is not read as a header
+        let tricky = BLAME.replace("\tlet end = start + len;", "\tsummary not this");
+        assert_eq!(blame_of_line(&tricky, 1_700_000_000).as_deref(), Some("a1b2c3d Alice Example, just now: Fix off-by-one in scroll clamp"));
+    }
+
+    #[test]
+    fn parse_blame_marks_uncommitted_lines() {
+        let porcelain = "0000000000000000000000000000000000000000 3 3 1\nauthor Not Committed Yet\nauthor-time 1700000000\nsummary Version of src/main.rs from src/main.rs\n\tnew line\n";
+        assert_eq!(blame_of_line(porcelain, 1_700_000_000).as_deref(), Some("not committed"));
+    }
+
+    #[test]
+    fn parse_blame_rejects_incomplete_output() {
+        assert_eq!(blame_of_line("", 0), None);
+        assert_eq!(parse_blame("a1b2c3 1 1 1\n", 0), None); // too short for a hash
+        let no_time: String = BLAME.lines().filter(|l| !l.starts_with("author-time")).map(|l| format!("{}\n", l)).collect();
+        assert_eq!(parse_blame(&no_time, 1_700_000_000), None);
+    }
+
+    #[test]
+    fn parse_blame_reuses_the_headers_of_a_commit_seen_before() {
+        let second = "a1b2c3d4e5f60718293a4b5c6d7e8f9012345678 13 14\n\tlet start = 0;\n";
+        let uncommitted = "0000000000000000000000000000000000000000 1 13 1\nauthor Not Committed Yet\nauthor-time 1700000000\n\tnew\n";
+        let porcelain = format!("{}{}{}", BLAME, uncommitted, second);
+        let commit = "a1b2c3d Alice Example, just now: Fix off-by-one in scroll clamp".to_string();
+        assert_eq!(parse_blame(&porcelain, 1_700_000_000), Some(vec![(12, commit.clone()), (13, "not committed".to_string()), (14, commit)]));
+        assert_eq!(parse_blame(second, 1_700_000_000), None); // headers of a commit never seen
+    }
+
+    #[test]
+    fn blame_is_fetched_for_visible_lines_without_a_valid_cache() {
+        let text: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+        let mut editor = editor_with(&text.iter().map(String::as_str).collect::<Vec<_>>());
+        let visible = editor.text_rows();
+        assert_eq!(editor.blame_missing(), Some((1, visible)));
+        editor.cache_blame((1..=visible).map(|line| (line, format!("blame {}", line))).collect());
+        assert_eq!(editor.blame_missing(), None);
+        editor.row_offset = 5; // scrolled, only the lines coming into view
+        assert_eq!(editor.blame_missing(), Some((visible + 1, visible + 5)));
+        editor.row_offset = 0;
+        editor.rows[3].push('!'); // an edited line is blamed again, the ones around it keep theirs
+        assert_eq!(editor.blame_missing(), Some((4, 4)));
+        assert_eq!(editor.cached_blame(4), Some("blame 5"));
+        editor.rows.remove(50); // a removed line renumbers the ones after it
+        assert_eq!(editor.blame_missing(), Some((1, visible)));
+    }
+
+    #[test]
+    fn blame_annotations_follow_lines_that_fit() {
+        let mut editor = editor_with(&["short", &"x".repeat(200)]);
+        editor.show_line_numbers = false;
+        editor.cache_blame(vec![(1, "a1b2c3d Alice, 1 day ago: Fix".to_string()), (2, "b2c3d4e Bob, 2 days ago: Long".to_string())]);
+        let drawn = |editor: &Editor| editor.draw_rows().unwrap().iter().map(|line| strip_ansi(line).trim_end().to_string()).collect::<Vec<_>>();
+        assert_eq!(drawn(&editor)[0], "short");
+        editor.blame_visible = true;
+        let rows = drawn(&editor);
+        assert_eq!(rows[0], "short  a1b2c3d Alice, 1 day ago: Fix");
+        assert_eq!(rows[1], "x".repeat(editor.text_cols())); // no room after a line cut off by the screen
+        editor.rows[0] = "y".repeat(editor.text_cols() - 10);
+        assert_eq!(drawn(&editor)[0], editor.rows[0]); // edited since it was blamed
+        editor.cache_blame(vec![(1, "a1b2c3d Alice, 1 day ago: Fix".to_string())]);
+        assert_eq!(drawn(&editor)[0], format!("{}  a1b2c3d", editor.rows[0])); // as much as fits
+    }
+
+    #[test]
+    fn relative_time_uses_the_largest_whole_unit() {
+        assert_eq!(relative_time(-5), "just now"); // a commit from a clock running ahead
+        assert_eq!(relative_time(0), "just now");
+        assert_eq!(relative_time(59), "just now");
+        assert_eq!(relative_time(60), "1 minute ago");
+        assert_eq!(relative_time(119), "1 minute ago");
+        assert_eq!(relative_time(3599), "59 minutes ago");
+        assert_eq!(relative_time(3600), "1 hour ago");
+        assert_eq!(relative_time(86399), "23 hours ago");
+        assert_eq!(relative_time(86400), "1 day ago");
+        assert_eq!(relative_time(7 * 86400 - 1), "6 days ago");
+        assert_eq!(relative_time(7 * 86400), "1 week ago");
+        assert_eq!(relative_time(30 * 86400), "1 month ago");
+        assert_eq!(relative_time(364 * 86400), "12 months ago");
+        assert_eq!(relative_time(365 * 86400), "1 year ago");
+        assert_eq!(relative_time(3 * 365 * 86400), "3 years ago");
+    }
+
+    // open the search prompt and type query into it
+    fn search(editor: &mut Editor, query: &str) {
+        editor.start_search();
+        for c in query.chars() {
+            editor.process_search_keypress(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+        }
+    }
+
+    fn search_key(editor: &mut Editor, code: KeyCode, modifiers: KeyModifiers) -> (usize, usize) {
+        editor.handle_key(KeyEvent::new(code, modifiers));
+        (editor.cursor_y, editor.cursor_x)
+    }
+
+    #[test]
+    fn search_steps_through_matches_on_one_line_and_wraps() {
+        let mut editor = editor_with(&["abab ab", "no hits here", "xAB"]);
+        search(&mut editor, "ab");
+        assert_eq!(editor.search_results, [(0, 0, 2), (0, 2, 4), (0, 5, 7), (2, 1, 3)]);
+        assert_eq!((editor.cursor_y, editor.cursor_x), (0, 0));
+        assert_eq!(search_key(&mut editor, KeyCode::Enter, KeyModifiers::NONE), (0, 2));
+        assert_eq!(search_key(&mut editor, KeyCode::Down, KeyModifiers::NONE), (0, 5));
+        assert_eq!(search_key(&mut editor, KeyCode::Enter, KeyModifiers::NONE), (2, 1)); // case-insensitive
+        assert_eq!(search_key(&mut editor, KeyCode::Enter, KeyModifiers::NONE), (0, 0));
+        assert!(editor.status_message.as_ref().is_some_and(|(msg, _)| msg == "Search wrapped to top"));
+        assert_eq!(search_key(&mut editor, KeyCode::Enter, KeyModifiers::SHIFT), (2, 1));
+        assert!(editor.status_message.as_ref().is_some_and(|(msg, _)| msg == "Search wrapped to bottom"));
+        assert_eq!(search_key(&mut editor, KeyCode::Up, KeyModifiers::NONE), (0, 5));
+    }
+
+    #[test]
+    fn search_prompt_ignores_unbound_chords() {
+        let mut editor = editor_with(&["a@b", "ab"]);
+        search(&mut editor, "a");
+        search_key(&mut editor, KeyCode::Char('x'), KeyModifiers::CONTROL);
+        search_key(&mut editor, KeyCode::Char('x'), KeyModifiers::ALT);
+        assert_eq!(editor.search_query, "a");
+        assert_eq!(search_key(&mut editor, KeyCode::Char('@'), KeyModifiers::CONTROL | KeyModifiers::ALT), (0, 0)); // AltGr
+        assert_eq!(editor.search_query, "a@");
+        assert_eq!(editor.search_results, [(0, 0, 2)]);
+    }
+
+    #[test]
+    fn search_overlapping_matches_on_one_line() {
+        let mut editor = editor_with(&["aaaa"]);
+        search(&mut editor, "aa");
+        assert_eq!(editor.search_results, [(0, 0, 2), (0, 1, 3), (0, 2, 4)]);
+    }
+
+    #[test]
+    fn search_with_zero_hits_keeps_the_cursor() {
+        let mut editor = editor_with(&["one", "two"]);
+        editor.cursor_y = 1;
+        editor.cursor_x = 2;
+        search(&mut editor, "three");
+        assert!(editor.search_results.is_empty());
+        assert_eq!(search_key(&mut editor, KeyCode::Enter, KeyModifiers::NONE), (1, 2));
+        assert_eq!(search_key(&mut editor, KeyCode::Up, KeyModifiers::NONE), (1, 2));
+        assert_eq!(search_key(&mut editor, KeyCode::Esc, KeyModifiers::NONE), (1, 2));
+        assert!(!editor.search_mode);
+    }
+
+    #[test]
+    fn search_esc_returns_to_where_it_started() {
+        let mut editor = editor_with(&["find me", "and me"]);
+        editor.cursor_y = 1;
+        editor.cursor_x = 3;
+        search(&mut editor, "me");
+        assert_eq!(search_key(&mut editor, KeyCode::Enter, KeyModifiers::NONE), (1, 4));
+        assert_eq!(search_key(&mut editor, KeyCode::Esc, KeyModifiers::NONE), (1, 3));
+    }
+
+    #[test]
+    fn display_path_prefers_the_shortest_form() {
+        let home = Path::new("/home/alice");
+        let cwd = Path::new("/home/alice/project");
+        let shown = |path: &str| display_path(Path::new(path), cwd, Some(home), PathDisplay::Shortest);
+        assert_eq!(shown("/home/alice/project/src/main.rs"), "src/main.rs"); // under the cwd
+        assert_eq!(shown("/home/alice/notes.txt"), "~/notes.txt"); // under home only
+        assert_eq!(shown("/etc/hosts"), "/etc/hosts"); // under neither
+        assert_eq!(shown("/home/alicex/file"), "/home/alicex/file"); // a sibling of home is not in it, ../../alicex/file ties
+        assert_eq!(display_path(Path::new("/home/alice/a"), home, Some(home), PathDisplay::Shortest), "a"); // cwd is home
+        // relative only while it is shorter than the ~ form
+        assert_eq!(display_path(Path::new("/home/alice/a"), Path::new("/"), Some(home), PathDisplay::Shortest), "~/a");
+        assert_eq!(display_path(Path::new("/home/alice/a"), Path::new("/home/bob"), None, PathDisplay::Shortest), "../alice/a");
+    }
+
+    #[test]
+    fn path_display_can_always_be_absolute_or_relative() {
+        let home = Some(Path::new("/home/alice"));
+        let cwd = Path::new("/home/alice/project");
+        let shown = |path: &str, policy| display_path(Path::new(path), cwd, home, policy);
+        assert_eq!(shown("/home/alice/project/src/main.rs", PathDisplay::Absolute), "~/project/src/main.rs");
+        assert_eq!(shown("/etc/hosts", PathDisplay::Absolute), "/etc/hosts");
+        assert_eq!(shown("/etc/hosts", PathDisplay::Relative), "../../../etc/hosts");
+        assert_eq!(shown("/home/alice/notes.txt", PathDisplay::Relative), "../notes.txt");
+        assert_eq!(shown("/home/alice/project", PathDisplay::Relative), ".");
+        assert_eq!(relative_path(Path::new("/"), cwd), Path::new("../../.."));
+    }
+
+    #[test]
+    fn paths_are_relative_to_a_symlinked_cwd() {
+        let dir = env::temp_dir().join(format!("rust_editor_cd_{}", std::process::id()));
+        let (real, link) = (dir.join("real"), dir.join("link"));
+        fs::create_dir_all(real.join("src")).unwrap();
+        fs::write(real.join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let mut editor = editor_with(&[""]);
+        editor.home = None;
+        press(&mut editor, KeyCode::Char('O'), KeyModifiers::ALT | KeyModifiers::SHIFT);
+        type_text(&mut editor, &link.to_string_lossy());
+        press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        let cwd = editor.cwd.clone();
+        press(&mut editor, KeyCode::Char('o'), KeyModifiers::ALT);
+        type_text(&mut editor, "src/main.rs"); // opened from the new cwd, not the process's
+        press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        let rows = editor.rows.clone();
+        editor.status_message = None; // the new working directory, in place of the file name
+        let mut out = Vec::new();
+        editor.draw_status_bar(&mut out).unwrap();
+        editor.path_display = PathDisplay::Absolute;
+        let mut absolute = Vec::new();
+        editor.draw_status_bar(&mut absolute).unwrap();
+        press(&mut editor, KeyCode::Char('O'), KeyModifiers::ALT | KeyModifiers::SHIFT);
+        type_text(&mut editor, "src/main.rs");
+        press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        let not_a_dir = editor.cwd.clone();
+        let canonical_real = fs::canonicalize(&real).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(cwd, canonical_real);
+        assert_eq!(rows, ["fn main() {}"]);
+        assert!(strip_ansi(&out).contains(" src/main.rs"));
+        assert!(strip_ansi(&absolute).contains(&canonical_real.join("src/main.rs").display().to_string()));
+        assert_eq!(not_a_dir, cwd);
+    }
+
+    // the text of drawn output without its escape sequences
+    fn strip_ansi(bytes: &[u8]) -> String {
+        let text = String::from_utf8_lossy(bytes);
+        let mut plain = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.find(|c| c.is_ascii_alphabetic()); // CSI sequences end with a letter
+            } else {
+                plain.push(c);
+            }
+        }
+        plain
+    }
+
+    // the text drawn on the warning background on a screen line
+    fn over_limit_text(editor: &Editor, line: &[u8]) -> String {
+        let warning = SetBackgroundColor(editor.theme.warning_bg).to_string();
+        let text = String::from_utf8_lossy(line);
+        let Some((_, after)) = text.split_once(&warning) else { return String::new() };
+        strip_ansi(after.split("\x1b[0m").next().unwrap_or_default().as_bytes())
+    }
+
+    fn commit_message(lines: &[&str]) -> Editor {
+        let mut editor = editor_with(lines);
+        editor.set_filename("COMMIT_EDITMSG".to_string());
+        editor.show_line_numbers = false;
+        editor.highlight_current_line = false;
+        editor.recompute_highlight_state();
+        editor
+    }
+
+    #[test]
+    fn commit_summary_past_50_columns_is_marked() {
+        let summary = format!("{}{}", "s".repeat(50), "OVER!");
+        let body = format!("{}{}", "b".repeat(72), "tail");
+        let editor = commit_message(&[&summary, "", &body, &format!("# {}", "c".repeat(80))]);
+        let screen = editor.draw_rows().unwrap();
+        assert!(strip_ansi(&screen[0]).starts_with(&summary));
+        assert_eq!(over_limit_text(&editor, &screen[0]), "OVER!");
+        assert_eq!(over_limit_text(&editor, &screen[1]), "");
+        assert_eq!(over_limit_text(&editor, &screen[2]), "tail");
+        assert_eq!(over_limit_text(&editor, &screen[3]), ""); // comments are stripped by git, no limit
+
+        let mut status = Vec::new();
+        editor.draw_status_bar(&mut status).unwrap();
+        assert!(strip_ansi(&status).trim_end().ends_with("55/50  Ln 1, Col 1"));
+    }
+
+    #[test]
+    fn commit_second_line_must_be_blank() {
+        let editor = commit_message(&["Summary", "not blank", "body"]);
+        let screen = editor.draw_rows().unwrap();
+        assert_eq!(over_limit_text(&editor, &screen[1]), "not blank");
+        assert_eq!(over_limit_text(&editor, &screen[2]), "");
+    }
+
+    #[test]
+    fn line_limits_come_from_the_config_per_file_type() {
+        let path = env::temp_dir().join(format!("rust_editor_limits_{}.toml", std::process::id()));
+        fs::write(&path, "[limits]\nrust = 100\nmarkdown = \"60, 80\"\ngit_commit = none\n").unwrap();
+        let mut config = Config::default();
+        let read = config.read_file(&path);
+        let _ = fs::remove_file(&path);
+        read.unwrap();
+        let mut editor = Editor::with_size(&config, 80, 24);
+        editor.rows = vec!["x".to_string(); 3];
+        editor.set_filename("lib.rs".to_string());
+        assert_eq!((editor.line_limit(0), editor.line_limit(2)), (Some(100), Some(100)));
+        editor.set_filename("README.md".to_string());
+        assert_eq!((editor.line_limit(0), editor.line_limit(1), editor.line_limit(2)), (Some(60), Some(80), Some(80)));
+        editor.set_filename("COMMIT_EDITMSG".to_string());
+        assert_eq!(editor.line_limit(0), None);
+        editor.set_filename("notes.txt".to_string());
+        assert_eq!(editor.line_limit(0), None);
+
+        fs::write(&path, "[limits]\ncobol = 72\n").unwrap();
+        let read = Config::default().read_file(&path);
+        let _ = fs::remove_file(&path);
+        assert!(read.is_err_and(|err| err.to_string() == "line 2: unknown file type 'cobol'"));
+    }
+
+    // read a config file with the given text over the defaults
+    fn read_config(text: &str) -> io::Result<Config> {
+        // a file per call, tests run in parallel
+        static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let call = CALLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("rust_editor_config_{}_{}.toml", std::process::id(), call));
+        fs::write(&path, text).unwrap();
+        let mut config = Config::default();
+        let read = config.read_file(&path);
+        let _ = fs::remove_file(&path);
+        read.map(|()| config)
+    }
+
+    #[test]
+    fn unquoted_hex_colors_are_values_not_comments() {
+        let config = read_config("theme = #1e1e1e\nautosave_interval = 30 # seconds\npath_display = relative\n[theme.mine]\ncomment = #202020 # dim\n").unwrap();
+        assert_eq!(config.theme.as_deref(), Some("#1e1e1e"));
+        assert_eq!(config.autosave_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.path_display, PathDisplay::Relative);
+        assert_eq!(config.themes[0].1.comment, Color::Rgb { r: 0x20, g: 0x20, b: 0x20 });
+        assert_eq!(read_config("autosave_interval = 0\n").unwrap().autosave_interval, None);
+    }
+
+    #[test]
+    fn empty_and_invalid_config_values_are_errors() {
+        let error = |text: &str| read_config(text).err().map(|err| err.to_string());
+        assert_eq!(error("tab_stop =\n").as_deref(), Some("line 1: no value for 'tab_stop'"));
+        assert_eq!(error("[theme.mine]\nkeyword = # later\n").as_deref(), Some("line 2: unknown color '# later'"));
+        assert_eq!(error("theme = \"\"\n").as_deref(), Some("line 1: no value for 'theme'"));
+        assert_eq!(error("autosave_interval = soon\n").as_deref(), Some("line 1: autosave_interval must be a number of seconds, 0 for off"));
+        assert_eq!(error("path_display = short\n").as_deref(), Some("line 1: path_display must be shortest, absolute or relative"));
+    }
+
+    #[test]
+    fn autosave_writes_modified_files_once_the_interval_passes() {
+        let path = env::temp_dir().join(format!("rust_editor_autosave_{}.txt", std::process::id()));
+        let mut editor = editor_with(&[""]);
+        editor.set_filename(path.to_string_lossy().into_owned());
+        type_text(&mut editor, "saved");
+        editor.autosave_interval = Some(Duration::from_secs(30));
+        editor.autosave_if_due();
+        assert!(editor.dirty && !path.exists()); // not yet
+        editor.last_autosave -= Duration::from_secs(31);
+        editor.autosave_if_due();
+        let written = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(written.ok().as_deref(), Some("saved"));
+        assert!(!editor.dirty);
+        assert_eq!(editor.autosave_timeout(), None); // nothing left to write
+    }
+
+    #[test]
+    fn typing_hello_world_is_two_undo_steps() {
+        let mut editor = editor_with(&[""]);
+        type_text(&mut editor, "hello world");
+        assert_eq!(editor.rows, ["hello world"]);
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.rows, ["hello"]);
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.rows, [""]);
+        assert!(editor.undo_stack.is_empty());
+    }
+
+    // xorshift, enough to pick keys without a dependency
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    #[test]
+    fn undoing_random_edits_restores_the_buffer() {
+        const NONE: KeyModifiers = KeyModifiers::NONE;
+        const SHIFT: KeyModifiers = KeyModifiers::SHIFT;
+        const CTRL: KeyModifiers = KeyModifiers::CONTROL;
+        const ALT: KeyModifiers = KeyModifiers::ALT;
+        let keys = [
+            (KeyCode::Char('a'), NONE), (KeyCode::Char('é'), NONE), (KeyCode::Char(' '), NONE), (KeyCode::Char('{'), NONE),
+            (KeyCode::Enter, NONE), (KeyCode::Backspace, NONE), (KeyCode::Delete, NONE), (KeyCode::Tab, NONE),
+            (KeyCode::BackTab, SHIFT), (KeyCode::Backspace, CTRL), (KeyCode::Delete, CTRL),
+            (KeyCode::Left, NONE), (KeyCode::Right, NONE), (KeyCode::Up, NONE), (KeyCode::Down, NONE),
+            (KeyCode::Left, SHIFT), (KeyCode::Right, SHIFT), (KeyCode::Up, SHIFT), (KeyCode::Down, SHIFT),
+            (KeyCode::Home, NONE), (KeyCode::End, NONE), (KeyCode::End, SHIFT),
+            (KeyCode::Char('x'), CTRL), (KeyCode::Char('x'), ALT), (KeyCode::Char('c'), ALT), (KeyCode::Char('v'), CTRL),
+            (KeyCode::Char('d'), CTRL), (KeyCode::Char('d'), ALT), (KeyCode::Char('k'), CTRL), (KeyCode::Char('e'), ALT),
+            (KeyCode::Char('z'), CTRL), (KeyCode::Char('y'), CTRL),
+        ];
+        let original = ["fn main() {", "\tlet x = 1 + 2;", "", "    世界 héllo  ", "}"];
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let mut editor = editor_with(&original);
+            for _ in 0..60 {
+                let (code, modifiers) = keys[rng.below(keys.len())];
+                press(&mut editor, code, modifiers);
+            }
+            while !editor.redo_stack.is_empty() {
+                press(&mut editor, KeyCode::Char('y'), CTRL); // the newest state the history reaches
+            }
+            let edited = editor.rows.clone();
+            while !editor.undo_stack.is_empty() {
+                press(&mut editor, KeyCode::Char('z'), CTRL);
+            }
+            assert_eq!(editor.rows, original);
+            while !editor.redo_stack.is_empty() {
+                press(&mut editor, KeyCode::Char('y'), CTRL);
+            }
+            assert_eq!(editor.rows, edited);
+        }
+    }
+
+    // the state every row starts in, worked out from the top without the cache
+    fn full_highlight_state(editor: &Editor) -> Vec<LineState> {
+        let mut state = LineState::Code;
+        editor.rows.iter().map(|line| {
+            let start = state;
+            state = editor.end_state(line, start);
+            start
+        }).collect()
+    }
+
+    #[test]
+    fn opening_a_comment_mid_burst_reaches_the_rows_below() {
+        let mut editor = editor_with(&["let a = 1;", "let b = 2;", "let c = 3;"]);
+        editor.language = Language::Rust;
+        editor.recompute_highlight_state();
+        type_text(&mut editor, "/*");
+        editor.recompute_highlight_state();
+        assert!(editor.highlight_state == [LineState::Code, LineState::BlockComment, LineState::BlockComment]);
+        press(&mut editor, KeyCode::Down, KeyModifiers::NONE);
+        type_text(&mut editor, "*/");
+        editor.recompute_highlight_state();
+        assert!(editor.highlight_state == [LineState::Code, LineState::BlockComment, LineState::Code]);
+    }
+
+    #[test]
+    fn highlight_state_follows_random_edits() {
+        let keys = [
+            (KeyCode::Char('/'), KeyModifiers::NONE), (KeyCode::Char('*'), KeyModifiers::NONE),
+            (KeyCode::Char('"'), KeyModifiers::NONE), (KeyCode::Char('a'), KeyModifiers::NONE),
+            (KeyCode::Enter, KeyModifiers::NONE), (KeyCode::Backspace, KeyModifiers::NONE),
+            (KeyCode::Delete, KeyModifiers::NONE), (KeyCode::Up, KeyModifiers::NONE), (KeyCode::Down, KeyModifiers::NONE),
+            (KeyCode::Left, KeyModifiers::NONE), (KeyCode::Right, KeyModifiers::NONE),
+            (KeyCode::Char('z'), KeyModifiers::CONTROL), (KeyCode::Char('y'), KeyModifiers::CONTROL),
+        ];
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let mut editor = editor_with(&["fn main() {", "    let s = \"x\";", "    /* note */", "}", ""]);
+            editor.language = Language::Rust;
+            for _ in 0..60 {
+                let (code, modifiers) = keys[rng.below(keys.len())];
+                press(&mut editor, code, modifiers);
+                editor.recompute_highlight_state(); // as each frame does
+                assert!(editor.highlight_state == full_highlight_state(&editor), "{:?}", editor.rows);
+            }
+        }
+    }
+
+    #[test]
+    fn brackets_in_comments_are_skipped_whatever_their_color() {
+        let mut editor = editor_with(&["fn f() { // }", "}"]);
+        editor.language = Language::Rust;
+        editor.theme.comment = editor.theme.normal;
+        editor.recompute_highlight_state();
+        assert_eq!(editor.bracket_positions(0), [(4, '('), (5, ')'), (7, '{')]);
+        assert_eq!(editor.find_matching_bracket(0, 7), Some((1, 0)));
+    }
+
+    // press Esc long enough after the last one not to make a double
+    fn escape(editor: &mut Editor) {
+        editor.last_escape = None;
+        editor.handle_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+    }
+
+    // a selection and the go-to prompt over it, with a quit waiting for its second Alt+q
+    fn editor_with_everything_open() -> Editor {
+        let mut editor = editor_with(&["hello"]);
+        editor.dirty = true;
+        press(&mut editor, KeyCode::Right, KeyModifiers::SHIFT);
+        editor.handle_key(KeyEvent::new(KeyCode::Char('g'), KeyModifiers::ALT));
+        editor.quit_pending = true; // any key that opens a prompt would have cancelled a real one
+        assert_eq!(editor.cancel_stack(), [Transient::Prompt, Transient::Selection, Transient::PendingQuit]);
+        editor
+    }
+
+    #[test]
+    fn esc_cancels_one_state_at_a_time_in_order() {
+        let mut editor = editor_with_everything_open();
+        escape(&mut editor);
+        assert!(!editor.goto_mode);
+        assert_eq!(editor.cancel_stack(), [Transient::Selection, Transient::PendingQuit]);
+        let mut status = Vec::new();
+        editor.draw_status_bar(&mut status).unwrap();
+        assert!(strip_ansi(&status).trim_end().ends_with("Esc: selection  Ln 1, Col 2"));
+        escape(&mut editor);
+        assert_eq!(editor.selection_anchor, None);
+        assert_eq!(editor.cancel_stack(), [Transient::PendingQuit]);
+        escape(&mut editor);
+        assert!(!editor.quit_pending);
+        assert!(editor.cancel_stack().is_empty());
+        assert!(!editor.handle_key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::ALT))); // asks again
+    }
+
+    #[test]
+    fn double_esc_cancels_everything() {
+        let mut editor = editor_with_everything_open();
+        editor.handle_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        editor.handle_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        assert!(editor.cancel_stack().is_empty());
+        assert_eq!(editor.rows, ["hello"]);
+    }
+
+    #[test]
+    fn esc_backs_out_of_the_overwrite_question_then_save_as() {
+        let path = env::temp_dir().join(format!("rust_editor_esc_{}.txt", std::process::id()));
+        fs::write(&path, "keep").unwrap();
+        let mut editor = editor_with(&["new"]);
+        editor.start_save_as();
+        editor.save_as_query = path.to_string_lossy().into_owned();
+        editor.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(editor.cancel_stack(), [Transient::Confirm, Transient::Prompt]);
+        escape(&mut editor);
+        assert_eq!(editor.input_prompt(), Some(format!("Save as: {}", path.display())));
+        escape(&mut editor);
+        assert!(editor.cancel_stack().is_empty());
+        let kept = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(kept.ok().as_deref(), Some("keep"));
+    }
+
+    #[test]
+    fn each_buffer_keeps_its_own_text_cursor_and_history() {
+        let mut editor = editor_with(&["first"]);
+        press(&mut editor, KeyCode::End, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Char('n'), KeyModifiers::ALT);
+        type_text(&mut editor, "second");
+        assert_eq!(editor.buffers[1].rows, ["second"]);
+        assert_eq!(editor.dirty_buffer_count(), 1);
+        press(&mut editor, KeyCode::Tab, KeyModifiers::ALT); // wraps around to the first
+        assert_eq!((editor.active_buffer, editor.cursor_x), (0, 5));
+        assert_eq!(editor.rows, ["first"]);
+        press(&mut editor, KeyCode::BackTab, KeyModifiers::ALT | KeyModifiers::SHIFT);
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.rows, [""]);
+        assert_eq!(editor.buffers[0].rows, ["first"]);
+
+        assert!(editor.show_line_numbers);
+        press(&mut editor, KeyCode::Char('N'), KeyModifiers::ALT | KeyModifiers::SHIFT);
+        assert!(!editor.show_line_numbers);
+        assert_eq!(editor.buffers.len(), 2);
+    }
+
+    #[test]
+    fn undo_steps_hold_only_the_ops_that_reverse_them() {
+        let mut editor = editor_with(&["ab", "cd"]);
+        editor.cursor_x = 1;
+        type_text(&mut editor, "é!");
+        press(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        editor.finish_undo_step();
+        assert_eq!(editor.undo_stack[0].ops, [
+            EditOp::DeleteChar { row: 0, col: 1, ch: 'é' },
+            EditOp::DeleteChar { row: 0, col: 1, ch: '!' },
+        ]);
+        assert_eq!(editor.undo_stack[1].ops, [EditOp::JoinLine { row: 0 }]);
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.redo_stack[0].ops, [EditOp::SplitLine { row: 0, col: 4 }]);
+        assert_eq!(editor.rows, ["aé!b", "cd"]);
+    }
+
+    #[test]
+    fn replace_all_records_only_the_rows_with_matches() {
+        let mut editor = editor_with(&["keep", "a x", "keep", "x b", "keep"]);
+        editor.start_replace();
+        search_key(&mut editor, KeyCode::Char('x'), KeyModifiers::NONE);
+        search_key(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        search_key(&mut editor, KeyCode::Char('y'), KeyModifiers::NONE);
+        search_key(&mut editor, KeyCode::Enter, KeyModifiers::NONE);
+        search_key(&mut editor, KeyCode::Char('a'), KeyModifiers::ALT);
+        assert_eq!(editor.rows, ["keep", "a y", "keep", "y b", "keep"]);
+        editor.finish_undo_step();
+        let step = &editor.undo_stack[0];
+        assert_eq!(step.row, 1);
+        assert_eq!(step.ops.len(), 4); // a char out and one in on each of the two rows
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        assert_eq!(editor.rows, ["keep", "a x", "keep", "x b", "keep"]);
+    }
+
+    fn pager_with(lines: &[&str]) -> Editor {
+        let mut editor = editor_with(lines);
+        editor.view_mode = true;
+        editor
+    }
+
+    #[test]
+    fn view_mode_pages_jumps_and_searches() {
+        let text: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+        let mut editor = pager_with(&text.iter().map(String::as_str).collect::<Vec<_>>());
+        press(&mut editor, KeyCode::Char(' '), KeyModifiers::NONE);
+        assert!(editor.cursor_y > 0 && editor.row_offset > 0);
+        press(&mut editor, KeyCode::Char('b'), KeyModifiers::NONE);
+        assert_eq!((editor.cursor_y, editor.row_offset), (0, 0));
+        press(&mut editor, KeyCode::Char('G'), KeyModifiers::NONE);
+        assert_eq!(editor.cursor_y, 99);
+        press(&mut editor, KeyCode::Char('g'), KeyModifiers::NONE);
+        assert_eq!((editor.cursor_y, editor.row_offset), (0, 0));
+        press(&mut editor, KeyCode::Char('/'), KeyModifiers::NONE);
+        type_text(&mut editor, "line 42");
+        assert_eq!((editor.cursor_y, editor.cursor_x), (42, 0));
+        assert_eq!(editor.rows, text); // the query went to the prompt, not the text
+    }
+
+    #[test]
+    fn view_mode_refuses_edits() {
+        let mut editor = pager_with(&["abc", "def"]);
+        type_text(&mut editor, "xyz");
+        for (code, mods) in [
+            (KeyCode::Enter, KeyModifiers::NONE),
+            (KeyCode::Backspace, KeyModifiers::NONE),
+            (KeyCode::Delete, KeyModifiers::NONE),
+            (KeyCode::Tab, KeyModifiers::NONE),
+            (KeyCode::Char('v'), KeyModifiers::CONTROL),
+            (KeyCode::Char('x'), KeyModifiers::CONTROL),
+            (KeyCode::Char('d'), KeyModifiers::ALT),
+            (KeyCode::Char('z'), KeyModifiers::CONTROL),
+        ] {
+            press(&mut editor, code, mods);
+        }
+        editor.finish_undo_step();
+        assert_eq!(editor.rows, ["abc", "def"]);
+        assert!(editor.undo_stack.is_empty() && editor.pending_undo.is_none());
+        assert!(!editor.dirty);
+    }
+
+    #[test]
+    fn follow_mode_reads_what_is_appended() {
+        let path = env::temp_dir().join(format!("rust_editor_follow_{}.log", std::process::id()));
+        let append = |text: &str| fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(text.as_bytes()).unwrap();
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let mut editor = pager_with(&["stale"]);
+        editor.filename = Some(path.to_string_lossy().into_owned());
+        press(&mut editor, KeyCode::Char('F'), KeyModifiers::NONE);
+        let at_start = (editor.rows.clone(), editor.cursor_y);
+        append("three\nfou");
+        editor.follow_file();
+        let partial = (editor.rows.clone(), editor.cursor_y);
+        append("r\n");
+        editor.follow_file();
+        let completed = editor.rows.clone();
+        press(&mut editor, KeyCode::Char('g'), KeyModifiers::NONE);
+        append("five\n");
+        editor.follow_file();
+        let not_at_end = (editor.rows.len(), editor.cursor_y);
+        fs::write(&path, "new\n").unwrap();
+        editor.follow_file();
+        let truncated = editor.rows.clone();
+        press(&mut editor, KeyCode::Char('F'), KeyModifiers::NONE);
+        append("ignored\n");
+        editor.follow_file();
+        let _ = fs::remove_file(&path);
+        assert_eq!(at_start, (lines("one\ntwo"), 1));
+        assert_eq!(partial, (lines("one\ntwo\nthree\nfou"), 3)); // stays at the end
+        assert_eq!(completed, lines("one\ntwo\nthree\nfour"));
+        assert_eq!(not_at_end, (5, 0)); // scrolled up, the view is left alone
+        assert_eq!(truncated, ["new"]);
+        assert!(!editor.follow);
+        assert_eq!(editor.rows, ["new"]);
+        assert!(!editor.dirty && editor.undo_stack.is_empty());
+    }
+
+    #[test]
+    fn typing_after_a_closed_step_is_still_recorded() {
+        let mut editor = editor_with(&[""]);
+        type_text(&mut editor, "a");
+        press(&mut editor, KeyCode::Char('y'), KeyModifiers::CONTROL); // redo with nothing to redo closes the step
+        press(&mut editor, KeyCode::Char('y'), KeyModifiers::CONTROL);
+        type_text(&mut editor, "b");
+        while !editor.undo_stack.is_empty() {
+            press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL);
+        }
+        assert_eq!(editor.rows, [""]);
+        while !editor.redo_stack.is_empty() {
+            press(&mut editor, KeyCode::Char('y'), KeyModifiers::CONTROL);
+        }
+        assert_eq!(editor.rows, ["ab"]);
+    }
+}